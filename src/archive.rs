@@ -0,0 +1,240 @@
+//! Safe unpacking of downloaded ZIP archives ("download all" bundles).
+//! Entries are never written to the path stored in the archive: only their
+//! base name is kept, and entries that try to escape (`../`, absolute
+//! paths), links and anything over the size limits are skipped.
+
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+use crate::{
+    config::DownloadConfig,
+    manifest::{sha256_file, sha256_hex},
+    models::DocumentType,
+    sniff::{detect_document, SNIFF_LEN},
+};
+
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+const DEFAULT_MAX_UNPACKED_MB: u64 = 2048;
+
+/// Bounds protecting against archive bombs.
+#[derive(Debug, Clone)]
+pub struct ArchiveLimits {
+    /// Entries looked at per archive, directories included
+    pub max_entries: usize,
+    /// Largest single file to extract
+    pub max_entry_bytes: Option<u64>,
+    /// Total bytes extracted per archive
+    pub max_total_bytes: u64,
+}
+
+impl ArchiveLimits {
+    pub fn new(
+        max_entries: Option<usize>,
+        max_entry_bytes: Option<u64>,
+        max_unpacked_mb: Option<u64>,
+    ) -> Self {
+        Self {
+            max_entries: max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+            max_entry_bytes,
+            max_total_bytes: max_unpacked_mb.unwrap_or(DEFAULT_MAX_UNPACKED_MB) * 1024 * 1024,
+        }
+    }
+
+    /// The limits of `[download]`: `max_archive_entries`, `max_file_size_mb`
+    /// per file and `max_unpacked_mb` in total.
+    pub fn from_config(dl: &DownloadConfig) -> Self {
+        Self::new(
+            dl.max_archive_entries,
+            dl.max_file_size_mb.map(|mb| mb * 1024 * 1024),
+            dl.max_unpacked_mb,
+        )
+    }
+}
+
+/// A document extracted into a temporary file, waiting for its final name.
+#[derive(Debug)]
+pub struct Member {
+    /// Path inside the archive, as stored there
+    pub inner_path: String,
+    pub doc_type: DocumentType,
+    pub temp: PathBuf,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Extract every entry of `archive` whose type is one of `types` (nested
+/// archives excluded) into temporary files in `output_dir`.
+/// Problems with single entries are reported through `warn` and skipped;
+/// hitting `max_entries` or `max_total_bytes` stops unpacking early.
+pub fn unpack(
+    archive: &Path,
+    output_dir: &Path,
+    types: &[DocumentType],
+    limits: &ArchiveLimits,
+    mut warn: impl FnMut(String),
+) -> Result<Vec<Member>> {
+    let file = fs::File::open(archive).with_context(|| format!("Cannot open {:?}", archive))?;
+    let mut zip = ZipArchive::new(file).with_context(|| format!("{:?} is not a valid ZIP", archive))?;
+
+    let mut members = Vec::new();
+    let mut total: u64 = 0;
+    for index in 0..zip.len() {
+        if index == limits.max_entries {
+            warn(format!("more than {} entries, the rest is ignored", limits.max_entries));
+            break;
+        }
+        let mut entry = zip.by_index(index)?;
+        let inner_path = entry.name().to_owned();
+        if entry.is_dir() {
+            continue;
+        }
+        // zip-slip: `../` or absolute paths
+        let absolute = inner_path.starts_with(['/', '\\']);
+        if absolute || entry.enclosed_name().is_none() || entry.is_symlink() {
+            warn(format!("`{inner_path}` points outside the archive, skipped"));
+            continue;
+        }
+        let Some(doc_type) = DocumentType::from_path(&inner_path)
+            .filter(|t| *t != DocumentType::Zip && types.contains(t))
+        else {
+            continue; // not a document we want
+        };
+
+        let temp = output_dir.join(format!(
+            ".{}.part",
+            &sha256_hex(format!("{}#{inner_path}", archive.display()).as_bytes())[..16]
+        ));
+        // never trust the size stored in the archive: count what comes out
+        let budget = limits
+            .max_entry_bytes
+            .unwrap_or(u64::MAX)
+            .min(limits.max_total_bytes - total);
+        let bytes = {
+            let mut out = fs::File::create(&temp)
+                .with_context(|| format!("Cannot write {:?}", temp))?;
+            let copied = io::copy(&mut (&mut entry).take(budget.saturating_add(1)), &mut out);
+            out.flush()?;
+            copied.with_context(|| format!("Cannot extract `{inner_path}`"))?
+        };
+        if bytes > budget {
+            let _ = fs::remove_file(&temp);
+            if let Some(max) = limits.max_entry_bytes.filter(|max| bytes > *max) {
+                warn(format!("`{inner_path}` is larger than {max} bytes, skipped"));
+                continue;
+            }
+            warn(format!(
+                "more than {} bytes unpacked, the rest is ignored",
+                limits.max_total_bytes
+            ));
+            break;
+        }
+        total += bytes;
+
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        fs::File::open(&temp)?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)?;
+        match detect_document(None, &head, &inner_path) {
+            Ok(detected) if detected == doc_type => {}
+            Ok(detected) => {
+                let _ = fs::remove_file(&temp);
+                warn(format!("`{inner_path}` is a {} file, skipped", detected.extension()));
+                continue;
+            }
+            Err(reason) => {
+                let _ = fs::remove_file(&temp);
+                warn(format!("`{inner_path}`: {reason}, skipped"));
+                continue;
+            }
+        }
+
+        members.push(Member {
+            inner_path,
+            doc_type,
+            sha256: sha256_file(&temp)?,
+            temp,
+            bytes,
+        });
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        fs::write(path, writer.finish().unwrap().into_inner()).unwrap();
+    }
+
+    #[test]
+    fn unpacks_documents_and_skips_unsafe_entries() {
+        let dir = Path::new("tmp_unpack_safe");
+        fs::create_dir_all(dir).unwrap();
+        let archive = dir.join("all.zip");
+        write_zip(
+            &archive,
+            &[
+                ("projects/a.pdf", b"%PDF-1.4 a"),
+                ("../../evil.pdf", b"%PDF-1.4 evil"),
+                ("/etc/passwd.pdf", b"%PDF-1.4 abs"),
+                ("readme.txt", b"hello"),
+                ("fake.pdf", b"<html>not a pdf</html>"),
+            ],
+        );
+
+        let mut warnings = Vec::new();
+        let limits = ArchiveLimits::new(None, None, None);
+        let members = unpack(&archive, dir, &[DocumentType::Pdf], &limits, |w| warnings.push(w)).unwrap();
+
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].inner_path, "projects/a.pdf");
+        assert_eq!(fs::read(&members[0].temp).unwrap(), b"%PDF-1.4 a");
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+        assert!(!Path::new("evil.pdf").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn size_limits_stop_archive_bombs() {
+        let dir = Path::new("tmp_unpack_limits");
+        fs::create_dir_all(dir).unwrap();
+        let archive = dir.join("bomb.zip");
+        let mut big = b"%PDF-1.4 ".to_vec();
+        big.resize(4096, b'0');
+        write_zip(&archive, &[("big.pdf", &big), ("small.pdf", b"%PDF-1.4 s")]);
+
+        let types = [DocumentType::Pdf];
+        let mut warnings = Vec::new();
+        let per_entry = ArchiveLimits {
+            max_entries: 10,
+            max_entry_bytes: Some(1024),
+            max_total_bytes: u64::MAX,
+        };
+        let members = unpack(&archive, dir, &types, &per_entry, |w| warnings.push(w)).unwrap();
+        assert_eq!(members.iter().map(|m| m.inner_path.as_str()).collect::<Vec<_>>(), ["small.pdf"]);
+
+        let total = ArchiveLimits {
+            max_entries: 10,
+            max_entry_bytes: None,
+            max_total_bytes: 100,
+        };
+        assert!(unpack(&archive, dir, &types, &total, |w| warnings.push(w)).unwrap().is_empty());
+        assert_eq!(warnings.len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
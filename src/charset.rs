@@ -0,0 +1,84 @@
+//! Character encoding of fetched pages, decided the way browsers do: a
+//! byte order mark wins, then the `charset` of the `Content-Type` header,
+//! then a `<meta charset>` or `http-equiv` declaration near the top of the
+//! document. Without any of these the body is UTF-8 if it decodes as such,
+//! else Windows-1252 (which also covers ISO-8859-1).
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use regex::bytes::Regex;
+use std::sync::OnceLock;
+
+/// How far into the body a `<meta>` declaration is looked for
+const PRESCAN_LEN: usize = 1024;
+
+/// Encoding of `body`, served with the `Content-Type` header `content_type`.
+pub fn detect(content_type: Option<&str>, body: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type.and_then(header_charset) {
+        return encoding;
+    }
+    if let Some(encoding) = meta_charset(body) {
+        return encoding;
+    }
+    if std::str::from_utf8(body).is_ok() {
+        UTF_8
+    } else {
+        WINDOWS_1252
+    }
+}
+
+/// `body` as text; malformed sequences become U+FFFD.
+pub fn decode(body: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode(body).0.into_owned()
+}
+
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches(['"', '\'']).as_bytes()))
+}
+
+fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    static META: OnceLock<Regex> = OnceLock::new();
+    let meta = META.get_or_init(|| {
+        Regex::new(r#"(?i-u)<meta\s[^>]*?charset\s*=\s*["']?\s*([a-z0-9_:.+-]+)"#).expect("valid regex")
+    });
+    let head = &body[..body.len().min(PRESCAN_LEN)];
+    let label = meta.captures(head)?.get(1)?.as_bytes();
+    let encoding = Encoding::for_label(label)?;
+    // a document that could read its own meta tag is not UTF-16
+    Some(if encoding == UTF_16LE || encoding == UTF_16BE { UTF_8 } else { encoding })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_encoding_from_bom_header_and_meta() {
+        let latin1 = b"<p>Universit\xe9 Libre de Bruxelles</p>";
+        assert_eq!(detect(Some("text/html; charset=ISO-8859-1"), latin1), WINDOWS_1252);
+        assert_eq!(detect(Some("text/html; charset=\"utf-8\""), b"<p>x</p>"), UTF_8);
+
+        let meta = b"<html><head><meta charset=\"windows-1252\"></head><p>Caf\xe9</p>";
+        assert_eq!(detect(Some("text/html"), meta), WINDOWS_1252);
+        let http_equiv =
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-2\"><p>\xb3</p>";
+        assert_eq!(detect(None, http_equiv).name(), "ISO-8859-2");
+        assert_eq!(detect(None, b"<meta charset=utf-16><p>x</p>"), UTF_8);
+
+        // the BOM beats the header, the header beats the meta tag
+        assert_eq!(detect(Some("text/html; charset=iso-8859-1"), b"\xef\xbb\xbf<p>\xc3\xa9</p>"), UTF_8);
+        assert_eq!(detect(Some("text/html; charset=utf-8"), meta), UTF_8);
+
+        // undeclared: UTF-8 if valid, else Windows-1252
+        assert_eq!(detect(None, "<p>Université</p>".as_bytes()), UTF_8);
+        assert_eq!(detect(None, latin1), WINDOWS_1252);
+        assert_eq!(decode(latin1, WINDOWS_1252), "<p>Université Libre de Bruxelles</p>");
+    }
+}
@@ -1,5 +1,7 @@
 use::clap::Parser;
 
+use crate::models::ContentMode;
+
 // Cli args for the web scraper
 #[derive(Parser, Debug)]
 #[command(author,version, about, long_about = None)]
@@ -12,14 +14,44 @@ pub struct CliArgs {
     #[arg(short, long)]
     pub timeout: Option<u64>,
 
-    // The CSS selector to extract elements
+    // The CSS selector (or `xpath:` expression) to extract elements
     #[arg(short, long)]
     pub selector: Option<String>,
 
+    /// How matched elements are stored: inner_html, outer_html, text or markdown
+    #[arg(long, value_enum)]
+    pub content_mode: Option<ContentMode>,
+
+    /// Scrape again even if a cached result for these inputs is still valid
     #[arg(long)]
     pub force_scrape: bool,
+
+    /// Delete all cached scrape results before running
+    #[arg(long)]
+    pub clear_cache: bool,
     
     /// Process extracted PDF texts into structured summary
     #[arg(long)]
     pub process_pdfs: bool,
+
+    /// Extract from stored page snapshots instead of the network; no downloads
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Write this run's HTTP traffic to a WARC file in `[warc].dir`
+    #[arg(long)]
+    pub warc: bool,
+
+    /// Replay the pages and downloads in this WARC file instead of the network
+    #[arg(long, value_name = "FILE", conflicts_with = "warc")]
+    pub replay_warc: Option<String>,
+
+    /// Serve pages and documents from this directory on a local port instead of the real site,
+    /// storing everything in backup/fixtures; with --offline, re-extract from that store
+    #[arg(long, value_name = "DIR", conflicts_with = "replay_warc")]
+    pub fixtures: Option<String>,
+
+    /// Fetch URLs even when robots.txt disallows them
+    #[arg(long)]
+    pub ignore_robots: bool,
 }
\ No newline at end of file
@@ -1,13 +1,187 @@
-use serde::Deserialize;
-use std::fs;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs};
 use anyhow::{Context, Result};
 
-#[derive(Deserialize, Debug)]
+use crate::{
+    link_rules::LinkRule,
+    models::{ContentMode, DocumentType},
+    schema::SchemaConfig,
+};
+
+#[derive(Deserialize, Debug, Default)]
 pub struct Config {
     pub url: Option<String>,
     pub timeout: Option<u64>,
     pub selector: Option<String>,
+    /// How elements matching `selector` are stored (default `inner_html`)
+    pub content_mode: Option<ContentMode>,
     pub pdf_selector: Option<String>,
+    /// Also HEAD every other link and keep those served as `application/pdf`
+    pub pdf_head_fallback: Option<bool>,
+    /// Which links count as documents; defaults to `<pdf_selector>="<type>"`
+    /// for every type in `download.attachment_types`
+    pub link_rules: Option<LinkRule>,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub download: DownloadConfig,
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    #[serde(default)]
+    pub crawl: CrawlConfig,
+    #[serde(default)]
+    pub sitemap: SitemapConfig,
+    /// Named fields extracted per container element into `records.json`
+    pub schema: Option<SchemaConfig>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub snapshots: SnapshotConfig,
+    #[serde(default)]
+    pub warc: WarcConfig,
+}
+
+/// Settings for the shared HTTP client (`[http]` table).
+/// The overall request timeout stays at the top level so `--timeout` can override it.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct HttpConfig {
+    /// Seconds allowed for establishing a TCP/TLS connection
+    pub connect_timeout: Option<u64>,
+    pub user_agent: Option<String>,
+    /// Extra headers sent with every request
+    pub headers: Option<HashMap<String, String>>,
+    /// Maximum redirects to follow; `0` disables redirects
+    pub max_redirects: Option<usize>,
+}
+
+/// Retry policy for the page fetch and downloads (`[retry]` table).
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct RetryConfig {
+    /// Total attempts per request, including the first one
+    pub max_attempts: Option<u32>,
+    /// Initial backoff in milliseconds, doubled on every attempt
+    pub backoff_base_ms: Option<u64>,
+    /// Upper bound for a single backoff (and for honoured `Retry-After`)
+    pub backoff_cap_ms: Option<u64>,
+    /// HTTP status codes worth retrying
+    pub retry_statuses: Option<Vec<u16>>,
+    /// Respect the server's `Retry-After` header on 429/503
+    pub respect_retry_after: Option<bool>,
+}
+
+/// Downloader settings (`[download]` table).
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct DownloadConfig {
+    /// Downloads running at the same time across all hosts
+    pub max_concurrent_downloads: Option<usize>,
+    /// Downloads running at the same time against a single host
+    pub max_per_host: Option<usize>,
+    /// Abandon downloads larger than this many MiB
+    pub max_file_size_mb: Option<u64>,
+    /// Exit with an error when more downloads than this fail
+    pub max_failures: Option<usize>,
+    /// Exit with an error when this share (0.0-1.0) of downloads fails
+    pub max_failure_ratio: Option<f64>,
+    /// Attachment types to download and extract; defaults to `["pdf"]`
+    pub attachment_types: Option<Vec<DocumentType>>,
+    /// Extract the documents inside downloaded ZIP archives (default `true`)
+    pub unpack_archives: Option<bool>,
+    /// Entries looked at per archive (default 10000)
+    pub max_archive_entries: Option<usize>,
+    /// Stop unpacking an archive after this many MiB (default 2048)
+    pub max_unpacked_mb: Option<u64>,
+}
+
+/// Following paginated listings (`[pagination]` table). Without
+/// `next_selector` or `url_template` only `url` is fetched.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct PaginationConfig {
+    /// CSS selector of the "Next" link; its `href` is the next page
+    pub next_selector: Option<String>,
+    /// Page URL with a `{page}` placeholder, e.g. `https://host/list?page={page}`
+    pub url_template: Option<String>,
+    /// First value of `{page}` (default 1)
+    pub start_page: Option<u32>,
+    /// Maximum number of pages to fetch (default 10)
+    pub max_pages: Option<usize>,
+}
+
+/// Site crawler (`[crawl]` table). Crawling replaces the single-page (or
+/// paginated) scrape when `max_depth` is set.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct CrawlConfig {
+    /// Link hops to follow from the start page; `0` visits only the start page
+    pub max_depth: Option<usize>,
+    /// Pages to fetch at most (default 100)
+    pub max_pages: Option<usize>,
+    /// Only visit URLs matching one of these regexes
+    pub include: Option<Vec<String>>,
+    /// Never visit URLs matching one of these regexes
+    pub exclude: Option<Vec<String>>,
+    /// Stay on the host of the start page (default `true`)
+    pub same_domain: Option<bool>,
+    /// Links to follow (default `a[href]`)
+    pub link_selector: Option<String>,
+    /// Where the frontier is saved for resuming (default `backup/crawl_state.json`)
+    pub state_file: Option<String>,
+}
+
+/// Seeding the scrape from a sitemap (`[sitemap]` table). When `url` is set
+/// the pages listed in the sitemap are scraped instead of `url` itself.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct SitemapConfig {
+    /// Sitemap or sitemap index, absolute or relative to `url` (e.g. `/sitemap.xml`)
+    pub url: Option<String>,
+    /// Only scrape pages whose URL matches one of these regexes
+    pub include: Option<Vec<String>>,
+    /// Never scrape pages whose URL matches one of these regexes
+    pub exclude: Option<Vec<String>>,
+    /// Skip entries whose `lastmod` is before this date (`YYYY-MM-DD`)
+    pub modified_since: Option<String>,
+    /// Pages to scrape at most (default 100)
+    pub max_pages: Option<usize>,
+    /// Sitemap files to read at most, counting nested ones (default 50)
+    pub max_sitemaps: Option<usize>,
+}
+
+/// Scrape result cache (`[cache]` table).
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct CacheConfig {
+    /// Directory of the cache entries (default `backup/scrape_cache`)
+    pub dir: Option<String>,
+    /// Hours a cached scrape stays valid (default 24)
+    pub ttl_hours: Option<u64>,
+}
+
+/// Raw page snapshots (`[snapshots]` table), replayed by `--offline`.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct SnapshotConfig {
+    /// Store every fetched page (default `true`)
+    pub enabled: Option<bool>,
+    /// Directory of the snapshots (default `backup/snapshots`)
+    pub dir: Option<String>,
+}
+
+/// WARC archive of the run's HTTP traffic (`[warc]` table).
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct WarcConfig {
+    /// Write one archive per run (default `false`; `--warc` also enables it)
+    pub enabled: Option<bool>,
+    /// Directory of the archives (default `backup/warc`)
+    pub dir: Option<String>,
+    /// Compress every record, giving `.warc.gz` files (default `true`)
+    pub gzip: Option<bool>,
+}
+
+impl DownloadConfig {
+    pub fn attachment_types(&self) -> Vec<DocumentType> {
+        self.attachment_types
+            .clone()
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| vec![DocumentType::Pdf])
+    }
 }
 
 pub fn load_config(file_path: &str) -> Result<Config> {
@@ -36,11 +210,72 @@ mod tests {
         let config = load_config("test_config.toml").unwrap();
         assert_eq!(config.url.unwrap(), "https://www.rust-lang.org");
         assert_eq!(config.timeout.unwrap(), 30);
+        assert_eq!(config.download.attachment_types(), vec![DocumentType::Pdf]);
 
         // Clean up
         fs::remove_file("test_config.toml").unwrap();
     }
 
+    #[test]
+    fn test_load_config_http_table() {
+        let config_content = r#"
+            url = "https://www.rust-lang.org"
+
+            [http]
+            connect_timeout = 5
+            user_agent = "edf-bot/1.0"
+            max_redirects = 3
+
+            [http.headers]
+            Accept-Language = "en"
+            "#;
+        fs::write("test_config_http.toml", config_content).unwrap();
+
+        let config = load_config("test_config_http.toml").unwrap();
+        assert_eq!(config.http.connect_timeout, Some(5));
+        assert_eq!(config.http.user_agent.as_deref(), Some("edf-bot/1.0"));
+        assert_eq!(config.http.max_redirects, Some(3));
+        let headers = config.http.headers.unwrap();
+        assert_eq!(headers.get("Accept-Language").unwrap(), "en");
+
+        fs::remove_file("test_config_http.toml").unwrap();
+    }
+
+    #[test]
+    fn test_load_config_link_rules() {
+        let config_content = r#"
+            url = "https://www.rust-lang.org"
+
+            [download]
+            attachment_types = ["pdf", "xlsx"]
+
+            [link_rules]
+            any = [
+                { href_suffix = ".pdf" },
+                { all = [{ type = "application/pdf" }, { css = "a.download" }] },
+            ]
+            "#;
+        fs::write("test_config_rules.toml", config_content).unwrap();
+
+        let config = load_config("test_config_rules.toml").unwrap();
+        assert_eq!(
+            config.link_rules,
+            Some(LinkRule::Any(vec![
+                LinkRule::HrefSuffix(".pdf".into()),
+                LinkRule::All(vec![
+                    LinkRule::Type("application/pdf".into()),
+                    LinkRule::Css("a.download".into()),
+                ]),
+            ]))
+        );
+        assert_eq!(
+            config.download.attachment_types(),
+            vec![DocumentType::Pdf, DocumentType::Xlsx]
+        );
+
+        fs::remove_file("test_config_rules.toml").unwrap();
+    }
+
     #[test]
     fn test_load_config_missing_file() {
         // Ensure no file exists
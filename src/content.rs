@@ -0,0 +1,99 @@
+//! Rendering an element's content in the configured `ContentMode`.
+
+use scraper::{ElementRef, Node};
+
+use crate::models::ContentMode;
+
+/// Elements that start a new line in the text rendering.
+const BLOCK_ELEMENTS: [&str; 34] = [
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption",
+    "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main",
+    "nav", "ol", "p", "pre", "section", "table", "tbody", "thead", "tfoot", "tr", "ul",
+];
+
+/// Elements whose text is never visible.
+const HIDDEN_ELEMENTS: [&str; 4] = ["script", "style", "template", "noscript"];
+
+/// `element` rendered as `mode`.
+pub fn render(element: ElementRef, mode: ContentMode) -> String {
+    match mode {
+        ContentMode::InnerHtml => element.inner_html(),
+        ContentMode::OuterHtml => element.html(),
+        ContentMode::Text => {
+            let mut text = String::new();
+            push_text(element, &mut text);
+            normalize_whitespace(&text)
+        }
+        // from the outer HTML, so a link keeps its target
+        ContentMode::Markdown => html2md::parse_html(&element.html()).trim().to_owned(),
+    }
+}
+
+/// Text of `element`'s descendants, block elements on their own lines and
+/// table cells separated by spaces.
+fn push_text(element: ElementRef, out: &mut String) {
+    for child in element.children() {
+        if let Node::Text(text) = child.value() {
+            // line breaks in the source are just whitespace
+            out.push_str(&text.replace(['\n', '\r'], " "));
+        } else if let Some(child) = ElementRef::wrap(child) {
+            let name = child.value().name();
+            if HIDDEN_ELEMENTS.contains(&name) {
+                continue;
+            }
+            let separator = if BLOCK_ELEMENTS.contains(&name) {
+                "\n"
+            } else if matches!(name, "td" | "th") {
+                " "
+            } else {
+                ""
+            };
+            out.push_str(separator);
+            push_text(child, out);
+            out.push_str(separator);
+        }
+    }
+}
+
+/// Runs of whitespace within a line become one space; lines are trimmed
+/// and blank lines dropped.
+pub fn normalize_whitespace(text: &str) -> String {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::{Html, Selector};
+
+    const HTML: &str = r#"<div class="item">
+        <h3>Call   <em>EDF-2024</em></h3>
+        <p>Read the
+           <a href="/guide">Contribution Guide</a></p>
+        <script>var x = 1;</script>
+        <ul><li>one</li><li>two</li></ul>
+    </div>"#;
+
+    fn render_item(mode: ContentMode) -> String {
+        let document = Html::parse_fragment(HTML);
+        let item = document.select(&Selector::parse("div.item").unwrap()).next().unwrap();
+        render(item, mode)
+    }
+
+    #[test]
+    fn renders_each_mode() {
+        assert!(render_item(ContentMode::InnerHtml).starts_with("\n        <h3>"));
+        assert!(render_item(ContentMode::OuterHtml).starts_with("<div class=\"item\">"));
+        assert_eq!(
+            render_item(ContentMode::Text),
+            "Call EDF-2024\nRead the Contribution Guide\none\ntwo"
+        );
+        let markdown = render_item(ContentMode::Markdown);
+        assert!(markdown.contains("### Call *EDF-2024*"), "{markdown}");
+        assert!(markdown.contains("[Contribution Guide](/guide)"), "{markdown}");
+    }
+}
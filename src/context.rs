@@ -0,0 +1,103 @@
+//! Where on its page an extracted element sits: a CSS-like DOM path, the
+//! nearest heading above it and the base URL its links resolve against.
+
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Path from the nearest ancestor with an `id` (or from `html`) down to
+/// `element`, e.g. `div#results > ul > li:nth-of-type(3) > a`.
+pub fn dom_path(element: ElementRef) -> String {
+    let mut segments = Vec::new();
+    let mut current = Some(element);
+    while let Some(el) = current {
+        let name = el.value().name();
+        if let Some(id) = el.value().id() {
+            segments.push(format!("{name}#{id}"));
+            break;
+        }
+        let same_tag = |sibling: &ElementRef| sibling.value().name() == name;
+        let before = el.prev_siblings().filter_map(ElementRef::wrap).filter(same_tag).count();
+        let after = el.next_siblings().filter_map(ElementRef::wrap).filter(same_tag).count();
+        segments.push(if before + after == 0 {
+            name.to_owned()
+        } else {
+            format!("{name}:nth-of-type({})", before + 1)
+        });
+        current = el.parent().and_then(ElementRef::wrap);
+    }
+    segments.reverse();
+    segments.join(" > ")
+}
+
+/// Text of the closest heading before `element` in document order, or of
+/// the heading containing it.
+pub fn nearest_heading(element: ElementRef) -> Option<String> {
+    let mut current = element;
+    loop {
+        for sibling in current.prev_siblings().filter_map(ElementRef::wrap) {
+            if let Some(heading) = last_heading(sibling) {
+                return Some(heading_text(heading));
+            }
+        }
+        current = current.parent().and_then(ElementRef::wrap)?;
+        if HEADINGS.contains(&current.value().name()) {
+            return Some(heading_text(current));
+        }
+    }
+}
+
+/// `element` itself if it is a heading, else the last heading inside it.
+fn last_heading(element: ElementRef) -> Option<ElementRef> {
+    if HEADINGS.contains(&element.value().name()) {
+        return Some(element);
+    }
+    element
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|el| HEADINGS.contains(&el.value().name()))
+        .last()
+}
+
+fn heading_text(heading: ElementRef) -> String {
+    heading.text().flat_map(str::split_whitespace).collect::<Vec<_>>().join(" ")
+}
+
+/// URL relative links of `document` resolve against: its `<base href>`, if
+/// any, joined with the page URL.
+pub fn document_base(document: &Html, page_url: &str) -> Option<Url> {
+    let page = Url::parse(page_url).ok()?;
+    let selector = Selector::parse("base[href]").expect("valid selector");
+    match document.select(&selector).next().and_then(|b| b.value().attr("href")) {
+        Some(href) => page.join(href).ok().or(Some(page)),
+        None => Some(page),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_elements() {
+        let document = Html::parse_document(
+            r#"<html><head><base href="/files/"></head><body>
+                <h1>Results</h1>
+                <div id="calls">
+                  <section><h2>EDF-2024-RA</h2><p>intro</p></section>
+                  <ul><li>a</li><li><a href="doc.pdf">PDF</a></li></ul>
+                </div>
+                <h3><a href="x">In a heading</a></h3>
+            </body></html>"#,
+        );
+        let links: Vec<_> = document.select(&Selector::parse("a").unwrap()).collect();
+        assert_eq!(dom_path(links[0]), "div#calls > ul > li:nth-of-type(2) > a");
+        assert_eq!(nearest_heading(links[0]).as_deref(), Some("EDF-2024-RA"));
+        assert_eq!(nearest_heading(links[1]).as_deref(), Some("In a heading"));
+        assert_eq!(dom_path(links[1]), "html > body > h3 > a");
+
+        let base = document_base(&document, "https://host/calls/list").unwrap();
+        assert_eq!(base.join("doc.pdf").unwrap().as_str(), "https://host/files/doc.pdf");
+    }
+}
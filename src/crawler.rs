@@ -0,0 +1,527 @@
+//! Breadth-first site crawler: starts from the landing page, follows links
+//! within the configured scope up to `max_depth` / `max_pages`, and runs the
+//! selector extraction on every visited page.
+//! The frontier is saved after each page and each page's results are
+//! appended to a side file, so an interrupted crawl resumes where it stopped
+//! on the next run. A saved crawl of another start URL or scope is discarded.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+use crate::{
+    config::CrawlConfig,
+    models::DocumentType,
+    fetch::PageFetcher,
+    manifest::sha256_hex,
+    scrape::{Extracted, Extractor},
+    selector::ElementSelector,
+};
+
+const DEFAULT_MAX_PAGES: usize = 100;
+const DEFAULT_STATE_FILE: &str = "backup/crawl_state.json";
+const DEFAULT_LINK_SELECTOR: &str = "a[href]";
+
+/// Links with these extensions are never worth fetching as pages.
+const SKIPPED_EXTENSIONS: [&str; 14] = [
+    "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "css", "js", "json", "xml", "mp3", "mp4",
+    "avi",
+];
+
+/// Query parameters that only track the visitor and never change the page.
+const TRACKING_PARAMS: [&str; 3] = ["utm_", "fbclid", "gclid"];
+
+/// Canonical form used for de-duplication: no fragment, no tracking
+/// parameters, remaining query parameters sorted, no empty `?`.
+/// Scheme and host are already lower-cased by `Url`, default ports dropped.
+pub fn normalize_url(mut url: Url) -> Url {
+    url.set_fragment(None);
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| !TRACKING_PARAMS.iter().any(|t| k.starts_with(t)))
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        pairs.sort();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url
+}
+
+/// Which URLs the crawler may visit.
+#[derive(Debug)]
+pub struct Scope {
+    host: Option<String>,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl Scope {
+    pub fn new(start: &Url, cfg: &CrawlConfig) -> Result<Self> {
+        let compile = |patterns: &Option<Vec<String>>| -> Result<Vec<Regex>> {
+            patterns
+                .iter()
+                .flatten()
+                .map(|p| Regex::new(p).with_context(|| format!("Invalid crawl pattern `{p}`")))
+                .collect()
+        };
+        Ok(Self {
+            host: cfg
+                .same_domain
+                .unwrap_or(true)
+                .then(|| start.host_str().unwrap_or_default().to_owned()),
+            include: compile(&cfg.include)?,
+            exclude: compile(&cfg.exclude)?,
+        })
+    }
+
+    /// HTTP(S), on the start host if `same_domain`, matching at least one
+    /// `include` pattern (when there are any) and no `exclude` pattern.
+    pub fn allows(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https")
+            && self.host.as_deref().is_none_or(|h| url.host_str() == Some(h))
+            && (self.include.is_empty() || self.include.iter().any(|re| re.is_match(url.as_str())))
+            && !self.exclude.iter().any(|re| re.is_match(url.as_str()))
+    }
+}
+
+/// Hash of the settings that decide which pages a crawl visits. `max_pages`
+/// is left out, so a crawl resumes after the limit is changed.
+fn scope_hash(cfg: &CrawlConfig) -> String {
+    let scope = (cfg.max_depth, &cfg.include, &cfg.exclude, cfg.same_domain, &cfg.link_selector);
+    let json = serde_json::to_vec(&scope).expect("crawl scope serializes");
+    sha256_hex(&json)[..16].to_owned()
+}
+
+/// Progress of a crawl, persisted between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CrawlState {
+    /// Normalized start URL
+    pub start: String,
+    /// `scope_hash` of the crawl settings
+    pub scope: String,
+    /// Pages still to visit, with their link depth from the start page
+    pub queue: VecDeque<(String, usize)>,
+    /// Pages visited or queued, normalized
+    pub seen: BTreeSet<String>,
+    pub pages_fetched: usize,
+    /// Kept in the results file next to the state file
+    #[serde(skip)]
+    pub extracted: Extracted,
+}
+
+/// One line of the results file: what the `page`-th fetched page yielded.
+#[derive(Serialize, Deserialize)]
+struct PageResult {
+    page: usize,
+    #[serde(flatten)]
+    extracted: Extracted,
+}
+
+impl CrawlState {
+    fn new(start: &Url, cfg: &CrawlConfig) -> Self {
+        let mut state = Self {
+            start: start.to_string(),
+            scope: scope_hash(cfg),
+            ..Default::default()
+        };
+        state.seen.insert(start.to_string());
+        state.queue.push_back((start.to_string(), 0));
+        state
+    }
+
+    fn results_path(path: &Path) -> PathBuf {
+        path.with_extension("results.jsonl")
+    }
+
+    /// The unfinished crawl saved at `path`, with the results of the pages
+    /// it counts as fetched.
+    fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = fs::File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+        let mut state: Self = serde_json::from_reader(file)
+            .with_context(|| format!("Cannot decode {}", path.display()))?;
+        // a finished crawl is not resumed, the next run starts over
+        if state.queue.is_empty() {
+            return Ok(None);
+        }
+
+        let results_path = Self::results_path(path);
+        if results_path.exists() {
+            let file = fs::File::open(&results_path)
+                .with_context(|| format!("Cannot open {}", results_path.display()))?;
+            // a page whose frontier update was lost is fetched again and
+            // logged twice; the later line wins
+            let mut pages = BTreeMap::new();
+            for line in BufReader::new(file).lines() {
+                let result: PageResult = serde_json::from_str(&line?)
+                    .with_context(|| format!("Cannot decode {}", results_path.display()))?;
+                if result.page <= state.pages_fetched {
+                    pages.insert(result.page, result.extracted);
+                }
+            }
+            for extracted in pages.into_values() {
+                state.extracted.extend(extracted);
+            }
+        }
+        Ok(Some(state))
+    }
+
+    /// Write the frontier; the results are appended page by page.
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = fs::File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Start an empty results file for a new crawl.
+    fn reset_results(path: &Path) -> Result<()> {
+        let results_path = Self::results_path(path);
+        if results_path.exists() {
+            fs::remove_file(&results_path)
+                .with_context(|| format!("Cannot remove {}", results_path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn append_result(path: &Path, result: &PageResult) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let results_path = Self::results_path(path);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&results_path)
+            .with_context(|| format!("Cannot open {}", results_path.display()))?;
+        let mut line = serde_json::to_vec(result)?;
+        line.push(b'\n');
+        file.write_all(&line)
+            .with_context(|| format!("Cannot write {}", results_path.display()))
+    }
+}
+
+/// Crawl from `start` and return what `extractor` finds on every visited
+/// page. Fetch failures, pages disallowed by robots.txt, pages redirected out
+/// of scope and non-HTML responses are logged and skipped. An unfinished crawl saved in
+/// `cfg.state_file` is resumed if it has the same start URL and scope.
+pub async fn crawl(
+    fetcher: &PageFetcher,
+    start: &str,
+    extractor: &Extractor,
+    cfg: &CrawlConfig,
+) -> Result<Extracted> {
+    let start = normalize_url(Url::parse(start).context("Invalid start URL")?);
+    let scope = Scope::new(&start, cfg)?;
+    let max_depth = cfg.max_depth.unwrap_or(0);
+    let max_pages = cfg.max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+    let css = cfg.link_selector.as_deref().unwrap_or(DEFAULT_LINK_SELECTOR);
+    let link_selector = ElementSelector::parse(css).context("Invalid crawl link_selector")?;
+    let state_path = Path::new(cfg.state_file.as_deref().unwrap_or(DEFAULT_STATE_FILE));
+
+    let mut state = match CrawlState::load(state_path)? {
+        Some(state) if state.start == start.as_str() && state.scope == scope_hash(cfg) => {
+            println!(
+                "Resuming crawl: {} page(s) done, {} queued",
+                state.pages_fetched,
+                state.queue.len()
+            );
+            state
+        }
+        saved => {
+            if let Some(saved) = saved {
+                println!(
+                    "Discarding the unfinished crawl of {}, it has another start URL or scope",
+                    saved.start
+                );
+            }
+            CrawlState::reset_results(state_path)?;
+            CrawlState::new(&start, cfg)
+        }
+    };
+
+    while let Some((page, depth)) = state.queue.pop_front() {
+        // `>=`: a resumed crawl may already be past a lowered limit
+        if state.pages_fetched >= max_pages {
+            println!("Stopping crawl after {max_pages} page(s)");
+            state.queue.clear();
+            break;
+        }
+        let url = Url::parse(&page)?;
+        state.pages_fetched += 1;
+
+        match fetcher.fetch_html(&url).await {
+            Ok(Some(page)) => {
+                // after a redirect the page, and what its links are relative
+                // to, is where the redirect ended up
+                let base = Url::parse(&page.final_url).unwrap_or_else(|_| url.clone());
+                let landed = normalize_url(base.clone());
+                if landed != url && !scope.allows(&landed) {
+                    println!("Skip {url}, it redirects out of the crawl to {base}");
+                    state.save(state_path)?;
+                    continue;
+                }
+                if landed != url && !state.seen.insert(landed.to_string()) {
+                    println!("Skip {url}, it redirects to {base}, which is crawled already");
+                    state.save(state_path)?;
+                    continue;
+                }
+                let html = page.text();
+                let found = extractor.extract(&html, base.as_str());
+                println!("Crawled {base} (depth {depth}): {found}");
+                let result = PageResult {
+                    page: state.pages_fetched,
+                    extracted: found,
+                };
+                CrawlState::append_result(state_path, &result)?;
+                state.extracted.extend(result.extracted);
+
+                if depth < max_depth {
+                    for link in page_links(&html, &link_selector, &base) {
+                        if scope.allows(&link) && state.seen.insert(link.to_string()) {
+                            state.queue.push_back((link.to_string(), depth + 1));
+                        }
+                    }
+                }
+            }
+            Ok(None) => println!("Skip {url}, not an HTML page"),
+            Err(e) => println!("Cannot crawl {url}: {e:#}"),
+        }
+        state.save(state_path)?;
+    }
+
+    state.save(state_path)?;
+    println!("Crawl finished: {} page(s), {}", state.pages_fetched, state.extracted);
+    Ok(state.extracted)
+}
+
+/// Normalized links of a page worth visiting as pages.
+fn page_links(html: &str, link_selector: &ElementSelector, page: &Url) -> Vec<Url> {
+    let document = Html::parse_document(html);
+    link_selector
+        .select(&document)
+        .into_iter()
+        .filter_map(|el| el.value().attr("href"))
+        .filter_map(|href| page.join(href).ok())
+        .filter(|url| {
+            let ext = Path::new(url.path())
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase());
+            ext.is_none_or(|e| {
+                DocumentType::from_extension(&e).is_none() && !SKIPPED_EXTENSIONS.contains(&e.as_str())
+            })
+        })
+        .map(normalize_url)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::ContentMode,
+        test_server::{serve, TestResponse},
+    };
+
+    #[test]
+    fn normalizes_urls_for_dedup() {
+        let url = |s: &str| normalize_url(Url::parse(s).unwrap()).to_string();
+        assert_eq!(url("HTTPS://Host:443/a?b=2&a=1#top"), "https://host/a?a=1&b=2");
+        assert_eq!(url("https://host/a?utm_source=x"), "https://host/a");
+        assert_eq!(url("https://host/a?"), "https://host/a");
+    }
+
+    #[test]
+    fn scope_rules() {
+        let start = Url::parse("https://host/results").unwrap();
+        let cfg = CrawlConfig {
+            include: Some(vec!["/results|/projects/".into()]),
+            exclude: Some(vec![r"\?lang=".into()]),
+            ..Default::default()
+        };
+        let scope = Scope::new(&start, &cfg).unwrap();
+        let allows = |s: &str| scope.allows(&Url::parse(s).unwrap());
+        assert!(allows("https://host/projects/42"));
+        assert!(!allows("https://other/projects/42"));
+        assert!(!allows("https://host/about"));
+        assert!(!allows("https://host/projects/42?lang=fr"));
+        assert!(!allows("mailto:someone@host"));
+    }
+
+    fn site() -> impl Fn(&crate::test_server::TestRequest) -> TestResponse {
+        |req| {
+            let html = |body: &str| {
+                TestResponse::new(200, format!("<html><body>{body}</body></html>"))
+                    .header("Content-Type", "text/html")
+            };
+            match req.path.as_str() {
+                "/" => html(r#"<a href="/p/1">1</a><a href="/p/2#x">2</a><a href="https://other/">o</a>"#),
+                "/p/1" => html(r#"<a href="/f/1.pdf" data-wt-preview="pdf">pdf</a><a href="/p/3">3</a>"#),
+                "/p/2" => html(r#"<a href="/f/2.pdf" data-wt-preview="pdf">pdf</a><a href="/">home</a>"#),
+                "/p/3" => html(r#"<a href="/f/3.pdf" data-wt-preview="pdf">pdf</a>"#),
+                _ => TestResponse::new(404, ""),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn crawls_within_depth() {
+        let base = serve(site()).await;
+        let cfg = CrawlConfig {
+            max_depth: Some(1),
+            state_file: Some("tmp_crawl_depth.json".into()),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(ElementSelector::parse("a[data-wt-preview]").unwrap(), ContentMode::default(), None);
+        let start = format!("{base}/");
+        let elements = crawl(&PageFetcher::for_tests(), &start, &extractor, &cfg)
+            .await
+            .unwrap()
+            .elements;
+
+        let found: Vec<_> = elements.iter().map(|e| e.attributes.as_ref().unwrap()["href"].as_str()).collect();
+        assert_eq!(found, ["/f/1.pdf", "/f/2.pdf"]); // /p/3 is depth 2
+        assert_eq!(elements[1].source_url.as_deref(), Some(format!("{base}/p/2").as_str()));
+
+        fs::remove_file("tmp_crawl_depth.json").unwrap();
+        fs::remove_file("tmp_crawl_depth.results.jsonl").unwrap();
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_within_scope_only() {
+        let base = serve(|req| {
+            let html = |body: &str| {
+                TestResponse::new(200, format!("<html><body>{body}</body></html>"))
+                    .header("Content-Type", "text/html")
+            };
+            let port = req.header("Host").unwrap().rsplit(':').next().unwrap().to_owned();
+            match req.path.as_str() {
+                "/" => html(r#"<a href="/old/">old</a><a href="/away">away</a>"#),
+                "/old/" => TestResponse::new(301, "").header("Location", "/new/"),
+                "/new/" => html(r#"<a href="doc.pdf" data-wt-preview="pdf">pdf</a><a href="next">next</a>"#),
+                "/new/next" => html(r#"<a href="next.pdf" data-wt-preview="pdf">pdf</a>"#),
+                "/away" => TestResponse::new(302, "").header("Location", &format!("http://localhost:{port}/x")),
+                "/x" => html(r#"<a href="/f/away.pdf" data-wt-preview="pdf">pdf</a>"#),
+                _ => TestResponse::new(404, ""),
+            }
+        })
+        .await;
+        let state_file = "tmp_crawl_redirects.json";
+        let cfg = CrawlConfig {
+            max_depth: Some(2),
+            state_file: Some(state_file.into()),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(ElementSelector::parse("a[data-wt-preview]").unwrap(), ContentMode::default(), None);
+        let elements = crawl(&PageFetcher::for_tests(), &format!("{base}/"), &extractor, &cfg)
+            .await
+            .unwrap()
+            .elements;
+
+        let found: Vec<_> = elements
+            .iter()
+            .map(|e| (e.attributes.as_ref().unwrap()["href"].as_str(), e.source_url.clone().unwrap()))
+            .collect();
+        assert_eq!(
+            found,
+            [("doc.pdf", format!("{base}/new/")), ("next.pdf", format!("{base}/new/next"))]
+        );
+
+        fs::remove_file(state_file).unwrap();
+        fs::remove_file(CrawlState::results_path(Path::new(state_file))).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_saved_frontier() {
+        let base = serve(site()).await;
+        let state_file = "tmp_crawl_resume.json";
+        let cfg = CrawlConfig {
+            max_depth: Some(5),
+            state_file: Some(state_file.into()),
+            ..Default::default()
+        };
+        // a previous run visited "/" and "/p/1", then stopped
+        let previous = CrawlState {
+            start: format!("{base}/"),
+            scope: scope_hash(&cfg),
+            queue: VecDeque::from([(format!("{base}/p/2"), 1), (format!("{base}/p/3"), 2)]),
+            seen: [format!("{base}/"), format!("{base}/p/1"), format!("{base}/p/2"), format!("{base}/p/3")]
+                .into_iter()
+                .collect(),
+            pages_fetched: 2,
+            extracted: Extracted::default(),
+        };
+        previous.save(Path::new(state_file)).unwrap();
+        let extractor = Extractor::new(ElementSelector::parse("a[data-wt-preview]").unwrap(), ContentMode::default(), None);
+        let start = format!("{base}/");
+        let elements = crawl(&PageFetcher::for_tests(), &start, &extractor, &cfg)
+            .await
+            .unwrap()
+            .elements;
+
+        assert_eq!(elements.len(), 2);
+        let state = CrawlState::load(Path::new(state_file)).unwrap();
+        assert!(state.is_none(), "finished crawls are not resumed");
+
+        fs::remove_file(state_file).unwrap();
+        fs::remove_file(CrawlState::results_path(Path::new(state_file))).unwrap();
+    }
+
+    #[tokio::test]
+    async fn discards_saved_crawl_of_another_site_and_honours_lowered_limit() {
+        let base = serve(site()).await;
+        let state_file = "tmp_crawl_other.json";
+        let path = Path::new(state_file);
+        let cfg = CrawlConfig {
+            max_depth: Some(5),
+            state_file: Some(state_file.into()),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(ElementSelector::parse("a[data-wt-preview]").unwrap(), ContentMode::default(), None);
+
+        // unfinished crawl of another site, with results on file
+        let mut other = CrawlState::new(&Url::parse("https://other.example/").unwrap(), &cfg);
+        other.pages_fetched = 1;
+        other.save(path).unwrap();
+        let stale = Extractor::new(ElementSelector::parse("p").unwrap(), ContentMode::default(), None)
+            .extract("<p>other site</p>", "https://other.example/");
+        CrawlState::append_result(path, &PageResult { page: 1, extracted: stale }).unwrap();
+        assert_eq!(CrawlState::load(path).unwrap().unwrap().extracted.elements.len(), 1);
+
+        let start = format!("{base}/");
+        let elements = crawl(&PageFetcher::for_tests(), &start, &extractor, &cfg)
+            .await
+            .unwrap()
+            .elements;
+        assert_eq!(elements.len(), 3);
+        assert!(elements.iter().all(|e| e.content != "other site"));
+
+        // resumed state already past a lowered limit stops right away
+        let mut past = CrawlState::new(&normalize_url(Url::parse(&start).unwrap()), &cfg);
+        past.pages_fetched = 5;
+        past.save(path).unwrap();
+        CrawlState::reset_results(path).unwrap();
+        let lowered = CrawlConfig { max_pages: Some(2), ..cfg.clone() };
+        assert_eq!(scope_hash(&lowered), scope_hash(&cfg), "max_pages keeps the scope");
+        let found = crawl(&PageFetcher::for_tests(), &start, &extractor, &lowered).await.unwrap();
+        assert!(found.is_empty());
+
+        fs::remove_file(path).unwrap();
+        let _ = fs::remove_file(CrawlState::results_path(path));
+    }
+}
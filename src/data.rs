@@ -3,12 +3,11 @@ use std::{fs, path::Path};
 use anyhow::Result;
 
 
-/// Serialize and save data to a JSON file inside the `backup` folder
-pub fn save_to_json<T: Serialize>(data: &T, file_path: &str) -> Result<()> {
+/// Serialize and save data to a JSON file inside the `backup_dir` folder
+pub fn save_to_json<T: Serialize>(data: &T, backup_dir: &str, file_path: &str) -> Result<()> {
     // Ensure backup folder exists
-    let backup_dir = "backup";
     if !Path::new(backup_dir).exists() {
-        fs::create_dir(backup_dir)
+        fs::create_dir_all(backup_dir)
         .expect("Failed to create backup directory");
     }
 
@@ -44,15 +43,17 @@ mod tests {
             tag: "p".to_string(),
             content: "This is a paragraph.".to_string(),
             attributes: Some(attributes),
+            ..Default::default()
         },
         ExtractedElement {
             tag: "h1".to_string(),
             content: "Title".to_string(),
             attributes: None,
+            ..Default::default()
         }];
 
         // Save data to JSON
-        save_to_json(&elements, "test_output.json").unwrap();
+        save_to_json(&elements, "backup", "test_output.json").unwrap();
 
         // Verify file exists and contains the expected data
         let content = fs::read_to_string("backup/test_output.json").unwrap();
@@ -1,123 +1,990 @@
-use anyhow::{anyhow, Context, Result};
-use reqwest::Client;
-use std::{collections::HashMap, fs, path::Path};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use reqwest::{
+    header::{
+        CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        IF_RANGE, LAST_MODIFIED, RANGE,
+    },
+    Response, StatusCode,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Semaphore,
+    task::JoinSet,
+};
+use scraper::Html;
 use url::Url;
 
-use crate::{config::Config, models::ExtractedElement};
+use crate::{
+    archive::{unpack, ArchiveLimits},
+    config::{Config, DownloadConfig},
+    fetch::PageFetcher,
+    filename::{content_disposition_filename, disambiguate, sanitize_filename, url_filename},
+    link_rules::{locate, InPage, LinkMatcher, LinkRule},
+    manifest::{sha256_file, sha256_hex, ArchiveMember, Manifest, ManifestEntry},
+    models::{DocumentType, DownloadOutcome, DownloadRecord, DownloadReport, ExtractedElement},
+    progress::{FileProgress, Progress},
+    snapshot::SnapshotStore,
+    retry::send_with_retry,
+    sniff::{detect_document, document_type_of, extension_for, SNIFF_LEN},
+    warc::{Exchange, Payload},
+};
 
-/// Read `json_path`, find <a> elements whose attribute `cfg.pdf_selector`
-/// (default `"data-wt-preview"`) indicates a PDF, and return absolute URLs.
-pub fn collect_pdf_links(json_path: &str, cfg: &Config) -> Result<Vec<Url>> {
-    // ------------------------------------------------------------------ load
+/// Sub-folder of the download directory for responses that are not documents.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Extensions that are certainly not documents; never worth a HEAD request.
+const NON_DOCUMENT_EXTENSIONS: [&str; 12] = [
+    "html", "htm", "css", "js", "png", "jpg", "jpeg", "gif", "svg", "ico", "xml", "json",
+];
+
+fn load_elements(json_path: &str) -> Result<Vec<ExtractedElement>> {
     let raw = fs::read_to_string(json_path)
         .with_context(|| format!("Cannot read {json_path}"))?;
+    serde_json::from_str(&raw).context("JSON deserialisation failed")
+}
 
-    let elements: Vec<ExtractedElement> =
-        serde_json::from_str(&raw).context("JSON deserialisation failed")?;
-
-    // ------------------------------------------------------------------ base
-    let base = Url::parse(
+fn base_url(cfg: &Config) -> Result<Url> {
+    Url::parse(
         cfg.url
             .as_deref()
             .ok_or_else(|| anyhow!("`url` missing in config"))?,
     )
-    .context("Invalid base URL")?;
+    .context("Invalid base URL")
+}
 
-    let pdf_attr = cfg
-        .pdf_selector
+/// Page an element was found on, for resolving its relative links.
+fn page_of(el: &ExtractedElement, base: &Url) -> Url {
+    el.source_url
         .as_deref()
-        .unwrap_or("data-wt-preview"); // sensible default
+        .and_then(|u| Url::parse(u).ok())
+        .unwrap_or_else(|| base.clone())
+}
 
+/// Absolute URL of an element's `href`: the one resolved at scrape time or,
+/// for older scrapes, the `href` joined with the element's page.
+fn link_of(el: &ExtractedElement, base: &Url) -> Option<Result<Url>> {
+    if let Some(Ok(url)) = el.resolved_url.as_deref().map(Url::parse) {
+        return Some(Ok(url));
+    }
+    let href = el.attributes.as_ref()?.get("href")?;
+    let page = page_of(el, base);
+    Some(page.join(href).with_context(|| format!("Cannot join {page} with {href}")))
+}
+
+/// Read `json_path`, find elements matching `cfg.link_rules` and return
+/// their `href`s as absolute URLs, resolved against the page each element
+/// came from and without duplicates. Without rules, a link qualifies when its
+/// attribute `cfg.pdf_selector` (default `"data-wt-preview"`) names one of
+/// the enabled attachment types (`"pdf"`, `"xlsx"` …). Every element in
+/// `json_path` was extracted with this run's selector, so none is skipped
+/// for its tag. `css` rules are checked in the snapshots of the pages the
+/// elements came from, so they can name ancestors.
+pub fn collect_pdf_links(json_path: &str, cfg: &Config) -> Result<Vec<Url>> {
+    // ------------------------------------------------------------------ load
+    let elements = load_elements(json_path)?;
+
+    // ------------------------------------------------------------------ base
+    let base = base_url(cfg)?;
 
-    let tag_filter = cfg.selector.as_deref();
+    let rule = cfg.link_rules.clone().unwrap_or_else(|| {
+        LinkRule::legacy(
+            cfg.pdf_selector.as_deref().unwrap_or("data-wt-preview"), // sensible default
+            &cfg.download.attachment_types(),
+        )
+    });
+    let matcher = LinkMatcher::compile(&rule).context("Invalid `link_rules`")?;
+    let pages = if matcher.needs_page() && cfg.snapshots.enabled.unwrap_or(true) {
+        source_pages(&elements, &SnapshotStore::new(&cfg.snapshots))
+    } else {
+        HashMap::new()
+    };
 
     let mut pdf_urls = Vec::new();
+    let mut seen = HashSet::new();
 
     for el in elements {
-        if let Some(tag) = tag_filter {
-            if el.tag != tag {
-                continue; // tag does not match selector
-            }
-        }
-
-        let attrs: &HashMap<String, String> = match &el.attributes {
-            Some(m) => m,
-            None => continue, // no attributes at all
+        let in_page = match el.source_url.as_ref().and_then(|url| pages.get(url)) {
+            Some(page) => locate(page, &el),
+            None => InPage::Unknown,
         };
+        if let InPage::Missing = in_page {
+            println!(
+                "Warning: `{}` is not in the snapshot of {}, `css` link rules do not match it",
+                el.dom_path.as_deref().unwrap_or_default(),
+                el.source_url.as_deref().unwrap_or_default()
+            );
+        }
+        if !matcher.matches(&el, in_page) {
+            continue;
+        }
 
-        // must have the attribute key at all
-        let value = match attrs.get(pdf_attr) {
-            Some(v) => v,
+        let url = match link_of(&el, &base) {
+            Some(url) => url?,
             None => continue,
         };
+        // the same document is often linked from several pages
+        if seen.insert(url.clone()) {
+            pdf_urls.push(url);
+        }
+    }
+
+    Ok(pdf_urls)
+}
 
-        // if the attribute has a value, enforce case-insensitive "pdf"
-        if !value.eq_ignore_ascii_case("pdf") {
+/// The snapshotted pages `elements` were extracted from, parsed, by URL.
+/// Pages without a snapshot are left out.
+fn source_pages(elements: &[ExtractedElement], store: &SnapshotStore) -> HashMap<String, Html> {
+    let mut pages = HashMap::new();
+    for source in elements.iter().filter_map(|el| el.source_url.as_ref()) {
+        if pages.contains_key(source) {
+            continue;
+        }
+        let Ok(url) = Url::parse(source) else {
             continue;
+        };
+        match store.load(&url) {
+            Ok(Some(page)) => {
+                pages.insert(source.clone(), Html::parse_document(&page.text()));
+            }
+            Ok(None) => {}
+            Err(e) => println!("Warning: cannot read the snapshot of {source}: {e:#}"),
         }
+    }
+    pages
+}
 
-        let href = match attrs.get("href") {
-            Some(h) => h,
-            None => continue,
+/// Fallback for links `collect_pdf_links` did not recognise: send a HEAD
+/// request to every other link in `json_path` and keep those the server
+/// reports as an enabled attachment type (`application/pdf` …).
+/// Links that fail or refuse HEAD, or that robots.txt disallows, are skipped.
+pub async fn confirm_links_with_head(
+    fetcher: &PageFetcher,
+    json_path: &str,
+    cfg: &Config,
+    known: &[Url],
+) -> Result<Vec<Url>> {
+    let elements = load_elements(json_path)?;
+    let base = base_url(cfg)?;
+    let types = cfg.download.attachment_types();
+
+    let mut seen: HashSet<Url> = known.iter().cloned().collect();
+    let mut confirmed = Vec::new();
+    for el in elements {
+        let Some(Ok(url)) = link_of(&el, &base) else {
+            continue;
         };
+        if !matches!(url.scheme(), "http" | "https") || !seen.insert(url.clone()) {
+            continue; // mailto:, javascript:, duplicates …
+        }
+        let ext = Path::new(url.path())
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+        if ext.is_some_and(|e| NON_DOCUMENT_EXTENSIONS.contains(&e.as_str())) {
+            continue;
+        }
 
-        let url = base
-            .join(href)
-            .with_context(|| format!("Cannot join {base} with {href}"))?;
-        pdf_urls.push(url);
+        match fetcher.head(&url).await {
+            Ok(page) => {
+                let doc_type = page
+                    .header("content-type")
+                    .and_then(document_type_of)
+                    .filter(|t| types.contains(t));
+                if let Some(doc_type) = doc_type {
+                    println!("HEAD confirmed {} link {url}", doc_type.extension());
+                    confirmed.push(url);
+                }
+            }
+            Err(e) => println!("HEAD {url} failed: {e:#}"),
+        }
     }
+    Ok(confirmed)
+}
 
-    Ok(pdf_urls)
+const DEFAULT_MAX_CONCURRENT: usize = 4;
+const DEFAULT_MAX_PER_HOST: usize = 2;
+
+/// Body fetched into a temporary file, waiting for its final name.
+#[derive(Debug)]
+struct Fetched {
+    http_status: u16,
+    doc_type: DocumentType,
+    bytes: u64,
+    temp: PathBuf,
+    sha256: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Name suggested by the server's `Content-Disposition` header
+    disposition_name: Option<String>,
+}
+
+/// What a response must satisfy to be kept.
+#[derive(Debug, Clone)]
+struct Accept {
+    /// Abandon bodies larger than this
+    max_bytes: Option<u64>,
+    /// Quarantine bodies that are not one of these
+    types: Arc<[DocumentType]>,
+}
+
+/// Outcome of a single successful download task.
+#[derive(Debug)]
+enum DownloadStatus {
+    Fetched(Fetched),
+    /// The server answered 304 Not Modified
+    NotModified,
+    /// The body is not an enabled document type and was moved to the
+    /// quarantine folder
+    Quarantined {
+        path: PathBuf,
+        reason: String,
+        http_status: u16,
+        bytes: u64,
+    },
 }
 
-/// Download every URL into `output_dir`.
-/// The filename is the last path segment; ".pdf" is appended if missing.
-pub async fn download_pdfs(urls: &[Url], output_dir: &str) -> Result<()> {
+/// Download every URL into `output_dir` using the fetcher's client, retrying
+/// transient failures according to its retry policy. URLs disallowed by
+/// robots.txt fail without a request, and each host's crawl delay is
+/// respected. When the fetcher writes a WARC archive, every response is
+/// recorded in it; when it replays one, the bodies come from the archive.
+/// At most `max_concurrent_downloads` transfers run at once, and at most
+/// `max_per_host` against the same host.
+/// A failing URL does not stop the others; every attempt ends up in the
+/// returned report.
+/// Files known to `manifest` are re-requested with a conditional GET and only
+/// rewritten when the server has a new version; a differing SHA-256 marks the
+/// entry as `changed` so the parser re-extracts it.
+/// New files are named after `Content-Disposition` or the last URL path
+/// segment, sanitized; a name already used by another URL gets a hash suffix.
+/// Names are assigned in URL order once all transfers finish, so the result
+/// does not depend on which download completes first.
+/// Bodies that are not one of `dl.attachment_types` are quarantined.
+/// Documents inside downloaded ZIP archives are unpacked next to them and
+/// recorded in `manifest` under `<archive url>#<inner path>`.
+pub async fn download_pdfs(
+    fetcher: &PageFetcher,
+    dl: &DownloadConfig,
+    manifest: &mut Manifest,
+    urls: &[Url],
+    output_dir: &str,
+) -> Result<DownloadReport> {
     fs::create_dir_all(output_dir)
         .with_context(|| format!("Cannot create {output_dir}"))?;
 
-    let client = Client::builder().build()?;
+    let started_at = Utc::now();
+    let global = Arc::new(Semaphore::new(
+        dl.max_concurrent_downloads.unwrap_or(DEFAULT_MAX_CONCURRENT).max(1),
+    ));
+    let per_host_limit = dl.max_per_host.unwrap_or(DEFAULT_MAX_PER_HOST).max(1);
+    let mut hosts: HashMap<String, Arc<Semaphore>> = HashMap::new();
+    let progress = Progress::new("downloads", urls.len() as u64);
+    let accept = Accept {
+        max_bytes: dl.max_file_size_mb.map(|mb| mb * 1024 * 1024),
+        types: dl.attachment_types().into(),
+    };
 
+    let mut tasks = JoinSet::new();
     for url in urls {
-        // --- derive target filename ----------------------------------------
-        let mut filename = url
-            .path_segments()
-            .and_then(|s| s.last())
-            .filter(|s| !s.is_empty())
-            .unwrap_or("download")
-            .to_owned();
-
-        if !filename.to_ascii_lowercase().ends_with(".pdf") {
-            filename.push_str(".pdf");
-        }
-
-        let path = Path::new(output_dir).join(&filename);
-        if path.exists() {
-            println!("Skip {}, already downloaded", path.display());
-            continue; // idempotent: do nothing
-        }
-
-        // --- perform download ----------------------------------------------
-        println!("Downloading {}", filename);
-        let bytes = client
-            .get(url.clone())
-            .send()
-            .await?
-            .error_for_status()?
-            .bytes()
-            .await?;
-
-        fs::write(&path, &bytes)
-            .with_context(|| format!("Cannot write {:?}", path))?;
-        println!("Saved {}", path.display());
+        let host_slot = hosts
+            .entry(url.host_str().unwrap_or_default().to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(per_host_limit)))
+            .clone();
+        let global = global.clone();
+        let fetcher = fetcher.clone();
+        let url = url.clone();
+        let output_dir = PathBuf::from(output_dir);
+        let previous = manifest.get(url.as_str()).cloned();
+        let accept = accept.clone();
+        let progress = progress.clone();
+
+        tasks.spawn(async move {
+            // host permit and the host's crawl delay first, so one slow host
+            // cannot hog the global pool
+            let _host = host_slot.acquire_owned().await?;
+            let started = Instant::now();
+            let result = match fetcher.admit(&url).await {
+                Ok(()) => {
+                    let _global = global.acquire_owned().await?;
+                    download_one(
+                        &fetcher,
+                        &url,
+                        &output_dir,
+                        previous.as_ref(),
+                        &accept,
+                        &progress,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            progress.file_done();
+            Ok::<_, anyhow::Error>((url, result, started.elapsed()))
+        });
+    }
+
+    let mut finished = Vec::with_capacity(urls.len());
+    while let Some(joined) = tasks.join_next().await {
+        finished.push(joined??);
+    }
+    progress.finish();
+    // name files in a fixed order regardless of completion order
+    finished.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+
+    let mut taken: HashMap<String, String> = manifest
+        .entries
+        .iter()
+        .map(|(url, e)| (e.file.to_lowercase(), url.clone()))
+        .collect();
+    let limits = ArchiveLimits::from_config(dl);
+    let mut records = Vec::with_capacity(finished.len());
+    for (url, result, elapsed) in finished {
+        let mut record = finalize(manifest, &mut taken, Path::new(output_dir), &url, result, elapsed);
+        let is_archive = DocumentType::from_path(&record.path) == Some(DocumentType::Zip);
+        if record.outcome == DownloadOutcome::Downloaded
+            && is_archive
+            && dl.unpack_archives != Some(false)
+        {
+            let unpacked = unpack_archive(
+                manifest,
+                &mut taken,
+                Path::new(output_dir),
+                &url,
+                &record.path,
+                &accept.types,
+                &limits,
+            );
+            match unpacked {
+                Ok(files) => record.unpacked = files,
+                Err(e) => println!("Warning: cannot unpack {}: {e:#}", record.path),
+            }
+        }
+        match record.outcome {
+            DownloadOutcome::Downloaded if record.changed => {
+                println!("Saved {} (content changed)", record.path)
+            }
+            DownloadOutcome::Downloaded => println!("Saved {}", record.path),
+            DownloadOutcome::Skipped => println!("Skip {}, not modified", record.path),
+            DownloadOutcome::Quarantined => println!(
+                "Warning: {} is not an accepted document ({}), quarantined as {}",
+                record.url,
+                record.error.as_deref().unwrap_or("unknown reason"),
+                record.path
+            ),
+            DownloadOutcome::Failed => println!(
+                "Failed {}: {}",
+                record.url,
+                record.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+        records.push(record);
+    }
+
+    let count = |o| records.iter().filter(|r| r.outcome == o).count();
+    let report = DownloadReport {
+        started_at,
+        downloaded: count(DownloadOutcome::Downloaded),
+        skipped: count(DownloadOutcome::Skipped),
+        failed: count(DownloadOutcome::Failed),
+        quarantined: count(DownloadOutcome::Quarantined),
+        records,
+    };
+    println!(
+        "Downloads finished: {} saved, {} skipped, {} failed, {} quarantined",
+        report.downloaded, report.skipped, report.failed, report.quarantined
+    );
+    Ok(report)
+}
+
+/// Fail the run if the report exceeds the configured failure threshold.
+/// Quarantined responses count as failures.
+/// Without `max_failures` / `max_failure_ratio` failures never abort the run.
+pub fn check_failure_threshold(report: &DownloadReport, dl: &DownloadConfig) -> Result<()> {
+    let failed = report.failed + report.quarantined;
+    if let Some(max) = dl.max_failures {
+        if failed > max {
+            bail!("{failed} download(s) failed, more than the allowed {max}");
+        }
+    }
+    if let Some(max) = dl.max_failure_ratio {
+        let ratio = report.failure_ratio();
+        if ratio > max {
+            bail!(
+                "{:.0}% of downloads failed, more than the allowed {:.0}%",
+                ratio * 100.0,
+                max * 100.0
+            );
+        }
     }
     Ok(())
 }
 
+/// Move a finished download into place, update `manifest` and describe the
+/// attempt for the report.
+fn finalize(
+    manifest: &mut Manifest,
+    taken: &mut HashMap<String, String>,
+    output_dir: &Path,
+    url: &Url,
+    result: Result<DownloadStatus>,
+    elapsed: Duration,
+) -> DownloadRecord {
+    let known_name = manifest.get(url.as_str()).map(|e| e.file.clone());
+    let mut record = DownloadRecord {
+        url: url.to_string(),
+        path: output_dir
+            .join(known_name.unwrap_or_else(|| sanitize_filename(&url_filename(url), url_type(url))))
+            .display()
+            .to_string(),
+        outcome: DownloadOutcome::Skipped,
+        http_status: None,
+        bytes: None,
+        duration_ms: elapsed.as_millis() as u64,
+        error: None,
+        changed: false,
+        unpacked: Vec::new(),
+    };
+
+    let stored = result.and_then(|status| match status {
+        DownloadStatus::NotModified => Ok(None),
+        DownloadStatus::Quarantined {
+            path,
+            reason,
+            http_status,
+            bytes,
+        } => {
+            record.outcome = DownloadOutcome::Quarantined;
+            record.path = path.display().to_string();
+            record.http_status = Some(http_status);
+            record.bytes = Some(bytes);
+            record.error = Some(reason);
+            Ok(None)
+        }
+        DownloadStatus::Fetched(fetched) => {
+            let http_status = fetched.http_status;
+            let bytes = fetched.bytes;
+            let temp = fetched.temp.clone();
+            store(manifest, taken, output_dir, url, fetched)
+                .inspect_err(|_| {
+                    let _ = fs::remove_file(&temp);
+                })
+                .map(|(path, changed)| Some((path, changed, http_status, bytes)))
+        }
+    });
+
+    match stored {
+        Ok(None) if record.outcome == DownloadOutcome::Quarantined => {}
+        Ok(None) => {
+            record.http_status = Some(StatusCode::NOT_MODIFIED.as_u16());
+        }
+        Ok(Some((path, changed, http_status, bytes))) => {
+            record.outcome = DownloadOutcome::Downloaded;
+            record.path = path.display().to_string();
+            record.http_status = Some(http_status);
+            record.bytes = Some(bytes);
+            record.changed = changed;
+        }
+        Err(e) => {
+            record.outcome = DownloadOutcome::Failed;
+            record.http_status = e
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|e| e.status())
+                .map(|s| s.as_u16());
+            record.error = Some(format!("{e:#}"));
+        }
+    }
+    record
+}
+
+/// Rename the temporary file to its final name (unless the content is
+/// identical to what we already have) and record it in `manifest`.
+/// Returns the final path and whether the content changed.
+fn store(
+    manifest: &mut Manifest,
+    taken: &mut HashMap<String, String>,
+    output_dir: &Path,
+    url: &Url,
+    fetched: Fetched,
+) -> Result<(PathBuf, bool)> {
+    let previous = manifest.get(url.as_str());
+    let name = match previous {
+        Some(prev) => prev.file.clone(),
+        None => claim_name(taken, url, fetched.disposition_name.as_deref(), fetched.doc_type),
+    };
+    let path = output_dir.join(&name);
+
+    // files downloaded before the manifest existed are hashed from disk
+    let old_sha256 = match previous {
+        Some(prev) => Some(prev.sha256.clone()),
+        None if path.exists() => Some(sha256_file(&path)?),
+        None => None,
+    };
+    let changed = old_sha256.as_ref().is_some_and(|old| *old != fetched.sha256);
+
+    if path.exists() && !changed {
+        fs::remove_file(&fetched.temp)
+            .with_context(|| format!("Cannot remove {:?}", fetched.temp))?;
+    } else {
+        fs::rename(&fetched.temp, &path)
+            .with_context(|| format!("Cannot move {:?} to {:?}", fetched.temp, path))?;
+    }
+
+    let entry = ManifestEntry {
+        file: name,
+        sha256: fetched.sha256,
+        etag: fetched.etag,
+        last_modified: fetched.last_modified,
+        fetched_at: Utc::now(),
+        // keep an earlier, not yet parsed change
+        changed: changed || previous.is_some_and(|p| p.changed),
+        archive: None,
+    };
+    manifest.insert(url.to_string(), entry);
+    Ok((path, changed))
+}
+
+/// Extract the documents of the archive downloaded from `url` and store each
+/// one like a download of its own, keyed by `url#inner/path`.
+/// Returns the paths of the extracted files.
+fn unpack_archive(
+    manifest: &mut Manifest,
+    taken: &mut HashMap<String, String>,
+    output_dir: &Path,
+    url: &Url,
+    archive: &str,
+    types: &[DocumentType],
+    limits: &ArchiveLimits,
+) -> Result<Vec<String>> {
+    let members = unpack(Path::new(archive), output_dir, types, limits, |warning| {
+        println!("Warning: {archive}: {warning}")
+    })?;
+
+    let mut files = Vec::with_capacity(members.len());
+    for member in members {
+        let mut key = url.clone();
+        key.set_fragment(Some(&member.inner_path));
+        let temp = member.temp.clone();
+        let fetched = Fetched {
+            http_status: StatusCode::OK.as_u16(),
+            doc_type: member.doc_type,
+            bytes: member.bytes,
+            temp: member.temp,
+            sha256: member.sha256,
+            etag: None,
+            last_modified: None,
+            disposition_name: Some(member.inner_path.clone()),
+        };
+        let (path, _) = store(manifest, taken, output_dir, &key, fetched).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })?;
+        if let Some(entry) = manifest.entries.get_mut(key.as_str()) {
+            entry.archive = Some(ArchiveMember {
+                archive_url: url.to_string(),
+                inner_path: member.inner_path,
+            });
+        }
+        files.push(path.display().to_string());
+    }
+    println!("Unpacked {} document(s) from {archive}", files.len());
+    Ok(files)
+}
+
+/// Pick a file name for a URL not yet in the manifest and reserve it.
+fn claim_name(
+    taken: &mut HashMap<String, String>,
+    url: &Url,
+    disposition: Option<&str>,
+    doc_type: DocumentType,
+) -> String {
+    let candidate = sanitize_filename(
+        &disposition.map(str::to_owned).unwrap_or_else(|| url_filename(url)),
+        doc_type,
+    );
+    let name = match taken.get(&candidate.to_lowercase()) {
+        Some(owner) if owner != url.as_str() => disambiguate(&candidate, url),
+        _ => candidate,
+    };
+    taken.insert(name.to_lowercase(), url.to_string());
+    name
+}
+
+/// Document type implied by the URL path, PDF when it says nothing.
+fn url_type(url: &Url) -> DocumentType {
+    DocumentType::from_path(url.path()).unwrap_or_default()
+}
+
+/// Keep a non-document body for inspection under `output_dir/quarantine/`.
+fn quarantine(output_dir: &Path, url: &Url, content_type: Option<&str>, part: &Path) -> Result<PathBuf> {
+    let dir = output_dir.join(QUARANTINE_DIR);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Cannot create {:?}", dir))?;
+
+    let name = sanitize_filename(&url_filename(url), url_type(url));
+    let stem = Path::new(&name).file_stem().unwrap_or_default().to_string_lossy();
+    let name = disambiguate(&format!("{stem}.{}", extension_for(content_type)), url);
+    let path = dir.join(name);
+    fs::rename(part, &path)
+        .with_context(|| format!("Cannot move {:?} to {:?}", part, path))?;
+    Ok(path)
+}
+
+/// First bytes of `path`, enough for magic-number sniffing.
+fn read_head(path: &Path) -> Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    fs::File::open(path)
+        .with_context(|| format!("Cannot open {:?}", path))?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+enum StreamError {
+    Network(reqwest::Error),
+    Disk(std::io::Error),
+    TooLarge(u64),
+}
+
+/// Append the response body to `file` chunk by chunk, counting into `written`.
+async fn stream_to_file(
+    response: &mut Response,
+    file: &mut File,
+    written: &mut u64,
+    max_bytes: Option<u64>,
+    bar: &mut FileProgress,
+) -> Result<(), StreamError> {
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                // what did arrive is resumed from and archived, so it must be on disk
+                file.flush().await.map_err(StreamError::Disk)?;
+                return Err(StreamError::Network(e));
+            }
+        };
+        if let Some(max) = max_bytes.filter(|max| *written + chunk.len() as u64 > *max) {
+            return Err(StreamError::TooLarge(max));
+        }
+        file.write_all(&chunk).await.map_err(StreamError::Disk)?;
+        *written += chunk.len() as u64;
+        bar.inc(chunk.len() as u64);
+    }
+    file.flush().await.map_err(StreamError::Disk)
+}
+
+/// Start offset of a `Content-Range: bytes <start>-<end>/<total>` header.
+pub fn content_range_start(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Fetch a single URL into a temporary `.part` file in `output_dir`,
+/// streaming the body to disk. A connection dropped mid-body is resumed with
+/// a `Range` request (guarded by `If-Range`) up to `retry.max_attempts` times.
+/// When `previous` describes a file that is still on disk, the request is
+/// conditional on its ETag / Last-Modified. Bodies larger than
+/// `accept.max_bytes` are abandoned, bodies that are not one of
+/// `accept.types` are quarantined.
+/// A `206` that does not continue at the resumed offset discards the partial
+/// file and the download starts over without `Range`.
+/// Every other response is recorded in the fetcher's WARC archive with the
+/// part of the body it delivered. When the fetcher replays an archive, the body comes
+/// from there instead.
+async fn download_one(
+    fetcher: &PageFetcher,
+    url: &Url,
+    output_dir: &Path,
+    previous: Option<&ManifestEntry>,
+    accept: &Accept,
+    progress: &Arc<Progress>,
+) -> Result<DownloadStatus> {
+    let (client, retry) = (fetcher.client(), fetcher.retry());
+    let max_bytes = accept.max_bytes;
+    // validators are worthless if the file itself is gone
+    let previous = previous.filter(|p| output_dir.join(&p.file).exists());
+    let temp = output_dir.join(format!(".{}.part", &sha256_hex(url.as_str().as_bytes())[..16]));
+    // a leftover from an aborted run may belong to an older version
+    if temp.exists() {
+        fs::remove_file(&temp)
+            .with_context(|| format!("Cannot remove stale {:?}", temp))?;
+    }
+
+    if let Some(archive) = fetcher.replay() {
+        if archive.not_modified(url) {
+            return Ok(DownloadStatus::NotModified);
+        }
+        let page = archive
+            .get(url)
+            .with_context(|| format!("{url} is not in the replayed WARC archive"))?;
+        let bytes = page.body.len() as u64;
+        if let Some(max) = max_bytes.filter(|&max| bytes > max) {
+            bail!("{url} is {bytes} bytes, more than the allowed {max}");
+        }
+        progress.println(format!("Replaying {}", url));
+        fs::write(&temp, &page.body).with_context(|| format!("Cannot write {:?}", temp))?;
+        let header = |name| page.header(name).map(str::to_owned);
+        let head = (
+            page.status,
+            header("etag"),
+            header("last-modified"),
+            page.header("content-disposition").and_then(content_disposition_filename),
+            header("content-type"),
+        );
+        return finish_download(url, output_dir, accept, temp, bytes, head);
+    }
+
+    let target = fetcher.target(url)?;
+    progress.println(format!("Downloading {}", url));
+    let mut bar = progress.start_file(&url_filename(url), None);
+    let mut attempt = 1;
+    // validator of the version being resumed, for `If-Range`
+    let mut resume_validator: Option<String> = None;
+    let mut written: u64 = 0;
+    let head = loop {
+        let mut headers = Vec::new();
+        if let Some(prev) = previous {
+            if let Some(etag) = &prev.etag {
+                headers.push((IF_NONE_MATCH, etag.clone()));
+            }
+            if let Some(modified) = &prev.last_modified {
+                headers.push((IF_MODIFIED_SINCE, modified.clone()));
+            }
+        }
+        if written > 0 {
+            headers.push((RANGE, format!("bytes={written}-")));
+            if let Some(v) = &resume_validator {
+                headers.push((IF_RANGE, v.clone()));
+            }
+        }
+        let offset = written;
+        let mut response = send_with_retry(retry, || {
+            headers
+                .iter()
+                .fold(client.get(target.clone()), |request, (name, value)| request.header(name, value))
+        })
+        .await?;
+        let mut exchange = fetcher.warc().is_some().then(|| {
+            let sent = headers.iter().map(|(k, v)| (k.as_str().to_owned(), v.clone())).collect();
+            Exchange::new(&target, sent, &response)
+        });
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            let _ = fs::remove_file(&temp);
+            if let (Some(warc), Some(exchange)) = (fetcher.warc(), &exchange) {
+                warc.record(exchange, Payload::Bytes(b""))
+                    .with_context(|| format!("Cannot archive {url} in {}", warc.path().display()))?;
+            }
+            return Ok(DownloadStatus::NotModified);
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        // resume only if the server continues exactly where we stopped
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let resumed = partial
+            && offset > 0
+            && header(CONTENT_RANGE).as_deref().and_then(content_range_start) == Some(offset);
+        if partial && !resumed {
+            // a range we did not ask for cannot complete the file: start over
+            let _ = fs::remove_file(&temp);
+            written = 0;
+            resume_validator = None;
+            if attempt < retry.max_attempts {
+                progress.println(format!(
+                    "{url} answered with a range not starting at byte {offset}, downloading it again"
+                ));
+                attempt += 1;
+                continue;
+            }
+            bail!("{url} answered with a range not starting at byte {offset}");
+        }
+        let head = (
+            if resumed { StatusCode::OK.as_u16() } else { response.status().as_u16() },
+            header(ETAG),
+            header(LAST_MODIFIED),
+            header(CONTENT_DISPOSITION)
+                .as_deref()
+                .and_then(content_disposition_filename),
+            header(CONTENT_TYPE),
+        );
+        resume_validator = head.1.clone().or_else(|| head.2.clone());
+
+        let mut file = if resumed {
+            OpenOptions::new().append(true).open(&temp).await
+        } else {
+            written = 0;
+            File::create(&temp).await
+        }
+        .with_context(|| format!("Cannot write {:?}", temp))?;
+
+        bar.set_position(written);
+        bar.set_length(response.content_length().map(|len| written + len));
+        if let (Some(max), Some(len)) = (max_bytes, response.content_length()) {
+            if written + len > max {
+                drop(file);
+                let _ = fs::remove_file(&temp);
+                bail!("{url} is {} bytes, more than the allowed {max}", written + len);
+            }
+        }
+
+        // ------------------------------------------------ stream body to disk
+        let streamed =
+            stream_to_file(&mut response, &mut file, &mut written, max_bytes, &mut bar).await;
+        drop(file);
+        if let (Some(warc), Some(exchange)) = (fetcher.warc(), &mut exchange) {
+            // what this response delivered, as it arrived
+            if matches!(streamed, Ok(()) | Err(StreamError::Network(_))) {
+                exchange.truncated = streamed.is_err();
+                let start = if resumed { offset } else { 0 };
+                warc.record(exchange, Payload::File(&temp, start))
+                    .with_context(|| format!("Cannot archive {url} in {}", warc.path().display()))?;
+            }
+        }
+        match streamed {
+            Ok(()) => break head,
+            Err(StreamError::Network(e)) if attempt < retry.max_attempts => {
+                let wait = retry.backoff(attempt);
+                progress.println(format!(
+                    "Connection to {url} dropped after {written} bytes ({e}), resuming in {:?}",
+                    wait
+                ));
+                tokio::time::sleep(wait).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(match e {
+                    StreamError::Network(e) => anyhow!(e).context(format!("Download of {url} interrupted")),
+                    StreamError::Disk(e) => anyhow!(e).context(format!("Cannot write {:?}", temp)),
+                    StreamError::TooLarge(max) => anyhow!("{url} exceeds the allowed {max} bytes"),
+                });
+            }
+        }
+    };
+
+    finish_download(url, output_dir, accept, temp, written, head)
+}
+
+/// Status, `ETag`, `Last-Modified`, `Content-Disposition` file name and
+/// `Content-Type` of a downloaded body.
+type ResponseHead = (u16, Option<String>, Option<String>, Option<String>, Option<String>);
+
+/// Check the type of the body fetched into `temp`, quarantining it if it is
+/// not an accepted document.
+fn finish_download(
+    url: &Url,
+    output_dir: &Path,
+    accept: &Accept,
+    temp: PathBuf,
+    written: u64,
+    head: ResponseHead,
+) -> Result<DownloadStatus> {
+    let (http_status, etag, last_modified, disposition_name, content_type) = head;
+    let name_hint = disposition_name.as_deref().unwrap_or(url.path());
+    let detected = detect_document(content_type.as_deref(), &read_head(&temp)?, name_hint)
+        .and_then(|t| {
+            if accept.types.contains(&t) {
+                Ok(t)
+            } else {
+                Err(format!("{} files are not enabled in `attachment_types`", t.extension()))
+            }
+        });
+    let doc_type = match detected {
+        Ok(t) => t,
+        Err(reason) => {
+            let path = quarantine(output_dir, url, content_type.as_deref(), &temp)?;
+            return Ok(DownloadStatus::Quarantined {
+                path,
+                reason,
+                http_status,
+                bytes: written,
+            });
+        }
+    };
+
+    Ok(DownloadStatus::Fetched(Fetched {
+        http_status,
+        doc_type,
+        bytes: written,
+        sha256: sha256_file(&temp)?,
+        temp,
+        etag,
+        last_modified,
+        disposition_name,
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
+    use crate::{
+        config::Config,
+        retry::RetryPolicy,
+        robots::Robots,
+        warc::{read_records, WarcArchive, WarcWriter},
+    };
+    use reqwest::Client;
+
+    fn no_robots() -> Arc<Robots> {
+        Arc::new(Robots::disabled())
+    }
+
+    #[test]
+    fn css_link_rules_match_ancestors_in_snapshotted_pages() {
+        let dir = "tmp_css_rule_snapshots";
+        let snapshots = crate::config::SnapshotConfig {
+            dir: Some(dir.into()),
+            ..Default::default()
+        };
+        let page = crate::snapshot::Page {
+            url: "https://host/list".into(),
+            final_url: "https://host/list".into(),
+            status: 200,
+            headers: vec![("content-type".into(), "text/html".into())],
+            fetched_at: Utc::now(),
+            charset: None,
+            body: br#"<ul class="downloads"><li><a href="/a.pdf">A</a></li></ul><p><a href="/b.pdf">B</a></p>"#
+                .to_vec(),
+        };
+        SnapshotStore::new(&snapshots).save(&page).unwrap();
+        let sample = r#"[
+            {"tag":"a","content":"A","attributes":{"href":"/a.pdf"},"source_url":"https://host/list","dom_path":"html > body > ul > li > a"},
+            {"tag":"a","content":"B","attributes":{"href":"/b.pdf"},"source_url":"https://host/list","dom_path":"html > body > p > a"}
+        ]"#;
+        fs::write("tmp_css_rule.json", sample).unwrap();
+
+        let cfg = Config {
+            url: Some("https://host/list".into()),
+            link_rules: Some(LinkRule::Css(".downloads a".into())),
+            snapshots,
+            ..Default::default()
+        };
+        let urls = collect_pdf_links("tmp_css_rule.json", &cfg).unwrap();
+        assert_eq!(urls, vec![Url::parse("https://host/a.pdf").unwrap()]);
+
+        fs::remove_file("tmp_css_rule.json").unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
 
     #[test]
     fn finds_pdf_links_with_custom_selector() {
@@ -127,6 +994,7 @@ mod tests {
             timeout: None,
             selector: Some("a".into()),          // we expect <a> tags
             pdf_selector: Some("data-wt-preview".into()),
+            ..Default::default()
         };
 
         // -------------------------------------------------- sample JSON file
@@ -142,6 +1010,568 @@ mod tests {
         assert_eq!(urls.len(), 1);
         assert_eq!(urls[0].as_str(), "https://host/f.pdf");
 
+        // the elements were extracted with the selector, whatever its form
+        let cfg = Config {
+            selector: Some("ul.files a.file".into()),
+            ..cfg
+        };
+        assert_eq!(collect_pdf_links("tmp.json", &cfg).unwrap(), urls);
+
         fs::remove_file("tmp.json").unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn finds_links_with_configured_rules() {
+        let cfg = Config {
+            url: Some("https://host/list/".into()),
+            selector: Some("a".into()),
+            link_rules: Some(LinkRule::Any(vec![
+                LinkRule::HrefSuffix(".pdf".into()),
+                LinkRule::Type("application/pdf".into()),
+            ])),
+            ..Default::default()
+        };
+
+        let sample = r#"[
+            {"tag":"a","content":"A","attributes":{"href":"a.pdf"}},
+            {"tag":"a","content":"B","attributes":{"href":"/b","type":"application/pdf"}},
+            {"tag":"a","content":"C","attributes":{"href":"/c","data-wt-preview":"pdf"}},
+            {"tag":"a","content":"D","attributes":{"href":"d.pdf"},"source_url":"https://host/list/2/"},
+            {"tag":"a","content":"B","attributes":{"href":"/b","type":"application/pdf"},"source_url":"https://host/list/2/"}
+        ]"#;
+        fs::write("tmp_rules.json", sample).unwrap();
+
+        let urls = collect_pdf_links("tmp_rules.json", &cfg).unwrap();
+        let urls: Vec<&str> = urls.iter().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            ["https://host/list/a.pdf", "https://host/b", "https://host/list/2/d.pdf"]
+        );
+
+        fs::remove_file("tmp_rules.json").unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_downloads_overall_and_per_host() {
+        use crate::test_server::{serve_async, TestResponse};
+        use std::sync::Mutex;
+
+        let dir = "tmp_download_limits";
+        fs::create_dir_all(dir).unwrap();
+
+        // requests in flight, overall and per `Host`, and the peaks seen
+        #[derive(Default)]
+        struct InFlight {
+            total: usize,
+            per_host: HashMap<String, usize>,
+            max_total: usize,
+            max_per_host: usize,
+        }
+        let in_flight = Arc::new(Mutex::new(InFlight::default()));
+        let counter = in_flight.clone();
+        let base = serve_async(move |req| {
+            let counter = counter.clone();
+            async move {
+                let host = req.header("Host").unwrap_or_default().split(':').next().unwrap().to_owned();
+                {
+                    let mut c = counter.lock().unwrap();
+                    c.total += 1;
+                    let on_host = c.per_host.entry(host.clone()).or_default();
+                    *on_host += 1;
+                    let on_host = *on_host;
+                    c.max_total = c.max_total.max(c.total);
+                    c.max_per_host = c.max_per_host.max(on_host);
+                }
+                // a slow server, so that downloads overlap
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let mut c = counter.lock().unwrap();
+                c.total -= 1;
+                *c.per_host.get_mut(&host).unwrap() -= 1;
+                TestResponse::new(200, "%PDF-1.4 doc").header("Content-Type", "application/pdf")
+            }
+        })
+        .await;
+
+        // the same server under two host names
+        let other = base.replace("127.0.0.1", "localhost");
+        let urls: Vec<Url> = (0..4)
+            .flat_map(|i| [format!("{base}/a{i}.pdf"), format!("{other}/b{i}.pdf")])
+            .map(|u| Url::parse(&u).unwrap())
+            .collect();
+        let dl = DownloadConfig {
+            max_concurrent_downloads: Some(3),
+            max_per_host: Some(2),
+            ..Default::default()
+        };
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(&PageFetcher::for_tests(), &dl, &mut manifest, &urls, dir)
+            .await
+            .unwrap();
+
+        assert_eq!(report.downloaded, 8, "{:?}", report.records);
+        let c = in_flight.lock().unwrap();
+        assert!(c.max_total <= 3, "{} downloads at once", c.max_total);
+        assert!(c.max_per_host <= 2, "{} downloads from one host at once", c.max_per_host);
+        assert!(c.max_total >= 2, "downloads never overlapped");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn conditional_get_detects_changed_files() {
+        use crate::test_server::{serve, TestResponse};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let dir = "tmp_download_manifest";
+        fs::create_dir_all(dir).unwrap();
+
+        // v1 on the first request, then 304 while the ETag matches, then v2
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let base = serve(move |req| match counter.fetch_add(1, Ordering::SeqCst) {
+            _ if req.path != "/a.pdf" => TestResponse::new(404, ""),
+            0 => TestResponse::new(200, "%PDF-1.4 v1").header("ETag", "\"v1\""),
+            1 if req.header("If-None-Match") == Some("\"v1\"") => TestResponse::new(304, ""),
+            _ => TestResponse::new(200, "%PDF-1.4 v2").header("ETag", "\"v2\""),
+        })
+        .await;
+
+        let urls = vec![Url::parse(&format!("{base}/a.pdf")).unwrap()];
+        let dl = DownloadConfig::default();
+        let fetcher = PageFetcher::for_tests();
+        let mut manifest = Manifest::default();
+
+        let first = download_pdfs(&fetcher, &dl, &mut manifest, &urls, dir).await.unwrap();
+        assert_eq!(first.downloaded, 1);
+        assert!(manifest.changed_files().is_empty());
+
+        let second = download_pdfs(&fetcher, &dl, &mut manifest, &urls, dir).await.unwrap();
+        assert_eq!(second.skipped, 1);
+
+        let third = download_pdfs(&fetcher, &dl, &mut manifest, &urls, dir).await.unwrap();
+        assert!(third.records[0].changed);
+        assert_eq!(manifest.changed_files(), HashSet::from(["a.pdf".to_string()]));
+        assert_eq!(fs::read_to_string(format!("{dir}/a.pdf")).unwrap(), "%PDF-1.4 v2");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported_instead_of_aborting() {
+        let dir = "tmp_download_fail";
+        fs::create_dir_all(dir).unwrap();
+
+        // the host does not resolve, so every request fails
+        let urls = vec![
+            Url::parse("http://invalid.test/a.pdf").unwrap(),
+            Url::parse("http://invalid.test/b.pdf").unwrap(),
+        ];
+        let retry = RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let dl = DownloadConfig::default();
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::new(Client::new(), retry, no_robots()),
+            &dl,
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.records.len(), 2);
+        assert_eq!(report.failed, 2);
+        let failed = &report.records[0];
+        assert_eq!(failed.outcome, DownloadOutcome::Failed);
+        assert!(failed.error.is_some());
+        assert!(manifest.entries.is_empty());
+
+        // no threshold configured: failures are tolerated
+        assert!(check_failure_threshold(&report, &dl).is_ok());
+        let strict = DownloadConfig {
+            max_failure_ratio: Some(0.25),
+            ..Default::default()
+        };
+        assert!(check_failure_threshold(&report, &strict).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn colliding_names_get_distinct_files() {
+        use crate::test_server::{serve, TestResponse};
+
+        let dir = "tmp_download_names";
+        fs::create_dir_all(dir).unwrap();
+
+        let base = serve(|req| match req.path.as_str() {
+            "/a/download" => TestResponse::new(200, "%PDF-1.4 a"),
+            "/b/download" => TestResponse::new(200, "%PDF-1.4 b"),
+            "/c/file" => TestResponse::new(200, "%PDF-1.4 c")
+                .header("Content-Disposition", r#"attachment; filename="../Fact sheet.pdf""#),
+            _ => TestResponse::new(404, ""),
+        })
+        .await;
+
+        let urls: Vec<Url> = ["a/download", "b/download", "c/file"]
+            .iter()
+            .map(|p| Url::parse(&format!("{base}/{p}")).unwrap())
+            .collect();
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::for_tests(),
+            &DownloadConfig::default(),
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.downloaded, 3);
+
+        let file_of = |u: &Url| manifest.get(u.as_str()).unwrap().file.clone();
+        assert_eq!(file_of(&urls[0]), "download.pdf");
+        assert_eq!(file_of(&urls[1]), disambiguate("download.pdf", &urls[1]));
+        assert_eq!(file_of(&urls[2]), "Fact sheet.pdf");
+        assert_eq!(
+            fs::read_to_string(Path::new(dir).join(file_of(&urls[1]))).unwrap(),
+            "%PDF-1.4 b"
+        );
+        // no temporary files left behind
+        assert!(fs::read_dir(dir)
+            .unwrap()
+            .all(|e| !e.unwrap().file_name().to_string_lossy().ends_with(".part")));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn html_responses_are_quarantined() {
+        use crate::test_server::{serve, TestResponse};
+
+        let dir = "tmp_download_quarantine";
+        fs::create_dir_all(dir).unwrap();
+
+        let base = serve(|_| {
+            TestResponse::new(200, "<html><body>Please log in</body></html>")
+                .header("Content-Type", "text/html; charset=utf-8")
+        })
+        .await;
+        let urls = vec![Url::parse(&format!("{base}/report.pdf")).unwrap()];
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::for_tests(),
+            &DownloadConfig::default(),
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.quarantined, 1);
+        let record = &report.records[0];
+        assert_eq!(record.outcome, DownloadOutcome::Quarantined);
+        assert!(record.path.contains(QUARANTINE_DIR) && record.path.ends_with(".html"));
+        assert!(Path::new(&record.path).exists());
+        assert!(!Path::new(dir).join("report.pdf").exists());
+        assert!(manifest.entries.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn enabled_attachment_types_are_kept() {
+        use crate::test_server::{serve, TestResponse};
+
+        let dir = "tmp_download_attachments";
+        fs::create_dir_all(dir).unwrap();
+
+        let base = serve(|req| match req.path.as_str() {
+            "/budget" => TestResponse::new(200, "PK\x03\x04 not really a workbook").header(
+                "Content-Type",
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+            _ => TestResponse::new(200, "partner,cost\nACME,1500\n")
+                .header("Content-Type", "text/csv"),
+        })
+        .await;
+        let urls = vec![
+            Url::parse(&format!("{base}/budget")).unwrap(),
+            Url::parse(&format!("{base}/partners.csv")).unwrap(),
+        ];
+        let dl = DownloadConfig {
+            attachment_types: Some(vec![DocumentType::Pdf, DocumentType::Xlsx]),
+            ..Default::default()
+        };
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::for_tests(),
+            &dl,
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((report.downloaded, report.quarantined), (1, 1));
+        assert!(Path::new(dir).join("budget.xlsx").exists());
+        let csv = &report.records[1];
+        assert_eq!(csv.outcome, DownloadOutcome::Quarantined);
+        assert!(csv.error.as_deref().unwrap().contains("csv"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn archives_are_unpacked_with_provenance() {
+        use crate::test_server::{serve, TestResponse};
+        use std::io::{Cursor, Write};
+        use zip::{write::SimpleFileOptions, ZipWriter};
+
+        let dir = "tmp_download_archive";
+        fs::create_dir_all(dir).unwrap();
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, body) in [("2024/a.pdf", "%PDF-1.4 a"), ("2023/a.pdf", "%PDF-1.4 old"), ("x.exe", "MZ")] {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(body.as_bytes()).unwrap();
+        }
+        let bundle = writer.finish().unwrap().into_inner();
+        let base = serve(move |_| {
+            TestResponse::new(200, bundle.clone()).header("Content-Type", "application/zip")
+        })
+        .await;
+
+        let urls = vec![Url::parse(&format!("{base}/all.zip")).unwrap()];
+        let dl = DownloadConfig {
+            attachment_types: Some(vec![DocumentType::Pdf, DocumentType::Zip]),
+            ..Default::default()
+        };
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::for_tests(),
+            &dl,
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.records[0].unpacked.len(), 2);
+        let inner = manifest.get(&format!("{base}/all.zip#2024/a.pdf")).unwrap();
+        let member = inner.archive.as_ref().unwrap();
+        assert_eq!(member.archive_url, urls[0].as_str());
+        assert_eq!(member.inner_path, "2024/a.pdf");
+        assert_eq!(fs::read_to_string(Path::new(dir).join(&inner.file)).unwrap(), "%PDF-1.4 a");
+        // same base name, different folder inside the archive
+        let other = manifest.get(&format!("{base}/all.zip#2023/a.pdf")).unwrap();
+        assert_ne!(other.file, inner.file);
+        assert!(!Path::new(dir).join("x.exe").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn head_fallback_confirms_unmarked_pdf_links() {
+        use crate::test_server::{serve, TestResponse};
+
+        let base = serve(|req| match req.path.as_str() {
+            "/doc" => TestResponse::new(200, "").header("Content-Type", "application/pdf"),
+            _ => TestResponse::new(200, "").header("Content-Type", "text/html"),
+        })
+        .await;
+
+        let cfg = Config {
+            url: Some(base.clone()),
+            selector: Some("a".into()),
+            ..Default::default()
+        };
+        let sample = r#"[
+            {"tag":"a","content":"x","attributes":{"href":"/doc"}},
+            {"tag":"a","content":"x","attributes":{"href":"/about"}},
+            {"tag":"a","content":"x","attributes":{"href":"/known.pdf"}},
+            {"tag":"a","content":"x","attributes":{"href":"/style.css"}},
+            {"tag":"a","content":"x","attributes":{"href":"mailto:info@example.org"}}
+        ]"#;
+        fs::write("tmp_head.json", sample).unwrap();
+
+        let known = vec![Url::parse(&format!("{base}/known.pdf")).unwrap()];
+        let confirmed = confirm_links_with_head(&PageFetcher::for_tests(), "tmp_head.json", &cfg, &known)
+            .await
+            .unwrap();
+        assert_eq!(confirmed, vec![Url::parse(&format!("{base}/doc")).unwrap()]);
+
+        fs::remove_file("tmp_head.json").unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_dropped_downloads_with_range() {
+        use crate::test_server::{serve, TestResponse};
+        use std::sync::Mutex;
+
+        let dir = "tmp_download_resume";
+        fs::create_dir_all(dir).unwrap();
+
+        let body: Vec<u8> = [b"%PDF-1.4\n".as_slice(), &[b'x'; 4000]].concat();
+        let expected = body.clone();
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let base = serve(move |req| {
+            let range = req.header("Range").map(str::to_owned);
+            seen.lock().unwrap().push(range.clone());
+            match range.as_deref().and_then(|r| r.strip_prefix("bytes=")) {
+                Some(r) => {
+                    let start: usize = r.trim_end_matches('-').parse().unwrap();
+                    assert_eq!(req.header("If-Range"), Some("\"v1\""));
+                    TestResponse::new(206, body[start..].to_vec())
+                        .header("ETag", "\"v1\"")
+                        .header(
+                            "Content-Range",
+                            &format!("bytes {start}-{}/{}", body.len() - 1, body.len()),
+                        )
+                }
+                None => TestResponse::new(200, body.clone())
+                    .header("ETag", "\"v1\"")
+                    .cut_after(1500),
+            }
+        })
+        .await;
+
+        let urls = vec![Url::parse(&format!("{base}/big.pdf")).unwrap()];
+        let retry = RetryPolicy {
+            backoff_base: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let warc = Path::new(dir).join("run.warc");
+        let writer = WarcWriter::create(&warc, &Default::default()).unwrap();
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::new(Client::new(), retry, no_robots()).with_warc(Arc::new(writer)),
+            &DownloadConfig::default(),
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.downloaded, 1, "{:?}", report.records);
+        assert_eq!(fs::read(Path::new(dir).join("big.pdf")).unwrap(), expected);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![None, Some("bytes=1500-".to_string())]
+        );
+
+        // each request is archived with the response it really got
+        let records = read_records(&warc).unwrap();
+        let block = |i: usize| String::from_utf8_lossy(&records[i].block).into_owned();
+        assert_eq!(records[2].header("WARC-Truncated"), Some("disconnect"));
+        assert!(!block(1).contains("range"));
+        assert!(block(2).starts_with("HTTP/1.1 200 OK"));
+        assert!(block(3).contains("range: bytes=1500-\r\nif-range: \"v1\""), "{}", block(3));
+        assert!(block(4).starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(block(4).contains(&format!("content-range: bytes 1500-{}/", expected.len() - 1)));
+        assert!(block(4).ends_with(&String::from_utf8_lossy(&expected[1500..]).into_owned()));
+        let replayed = WarcArchive::open(&warc).unwrap().get(&urls[0]).unwrap();
+        assert_eq!((replayed.status, replayed.body), (200, expected));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restarts_when_a_resume_gets_the_wrong_range() {
+        use crate::test_server::{serve, TestResponse};
+        use std::sync::Mutex;
+
+        let dir = "tmp_download_bad_range";
+        fs::create_dir_all(dir).unwrap();
+
+        let body: Vec<u8> = [b"%PDF-1.4\n".as_slice(), &[b'x'; 4000]].concat();
+        let expected = body.clone();
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let base = serve(move |req| {
+            let range = req.header("Range").map(str::to_owned);
+            let mut seen = seen.lock().unwrap();
+            seen.push(range.clone());
+            match (range, seen.len()) {
+                // the resume is answered from byte 100 instead of byte 1500
+                (Some(_), _) => TestResponse::new(206, body[100..].to_vec())
+                    .header("ETag", "\"v1\"")
+                    .header(
+                        "Content-Range",
+                        &format!("bytes 100-{}/{}", body.len() - 1, body.len()),
+                    ),
+                (None, 1) => TestResponse::new(200, body.clone())
+                    .header("ETag", "\"v1\"")
+                    .cut_after(1500),
+                (None, _) => TestResponse::new(200, body.clone()).header("ETag", "\"v1\""),
+            }
+        })
+        .await;
+
+        let urls = vec![Url::parse(&format!("{base}/big.pdf")).unwrap()];
+        let retry = RetryPolicy {
+            backoff_base: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::new(Client::new(), retry, no_robots()),
+            &DownloadConfig::default(),
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.downloaded, 1, "{:?}", report.records);
+        assert_eq!(fs::read(Path::new(dir).join("big.pdf")).unwrap(), expected);
+        assert_eq!(report.records[0].http_status, Some(200));
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![None, Some("bytes=1500-".to_string()), None]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_downloads_are_abandoned() {
+        use crate::test_server::{serve, TestResponse};
+
+        let dir = "tmp_download_too_big";
+        fs::create_dir_all(dir).unwrap();
+
+        let base = serve(|_| TestResponse::new(200, vec![b'x'; 1024 * 1024 + 1])).await;
+        let urls = vec![Url::parse(&format!("{base}/huge.pdf")).unwrap()];
+        let dl = DownloadConfig {
+            max_file_size_mb: Some(1),
+            ..Default::default()
+        };
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(
+            &PageFetcher::for_tests(),
+            &dl,
+            &mut manifest,
+            &urls,
+            dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.failed, 1);
+        assert!(report.records[0].error.as_deref().unwrap().contains("allowed"));
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0, "partial file left behind");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
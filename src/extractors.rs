@@ -0,0 +1,303 @@
+//! Plain-text extraction for every supported attachment type.
+//! Paragraphs become lines, table cells are separated by tabs and rows by
+//! newlines, so the output stays greppable by the same regexes as PDF text.
+
+use anyhow::{bail, Context, Result};
+use calamine::{Reader, Xlsx};
+use quick_xml::{
+    escape::resolve_predefined_entity,
+    events::Event,
+    Reader as XmlReader,
+};
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+use crate::{archive::ArchiveLimits, models::DocumentType};
+
+/// Extract the text of a document of type `doc_type` held in `bytes`. The
+/// XML inside DOCX and ODT files is unpacked within `limits`.
+pub fn extract_text(doc_type: DocumentType, bytes: &[u8], limits: &ArchiveLimits) -> Result<String> {
+    let raw = match doc_type {
+        DocumentType::Pdf => pdf_extract::extract_text_from_mem(bytes)?,
+        DocumentType::Docx => docx_text(&zip_entry(bytes, "word/document.xml", limits)?)?,
+        DocumentType::Odt => odt_text(&zip_entry(bytes, "content.xml", limits)?)?,
+        DocumentType::Xlsx => xlsx_text(bytes)?,
+        DocumentType::Csv => csv_text(bytes)?,
+        DocumentType::Zip => zip_listing(bytes)?,
+    };
+
+    // simple clean-up: drop leading/trailing whitespace, compress blanks
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn zip_entry(bytes: &[u8], name: &str, limits: &ArchiveLimits) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).context("Not a ZIP container")?;
+    let entry = archive
+        .by_name(name)
+        .with_context(|| format!("`{name}` missing from the container"))?;
+    // never trust the size stored in the container: count what comes out
+    let budget = limits.max_entry_bytes.unwrap_or(u64::MAX).min(limits.max_total_bytes);
+    let mut out = Vec::new();
+    entry.take(budget.saturating_add(1)).read_to_end(&mut out)?;
+    if out.len() as u64 > budget {
+        bail!("`{name}` unpacks to more than {budget} bytes");
+    }
+    Ok(out)
+}
+
+/// What an XML element contributes to the text around it.
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Newline,
+    /// Table cell or tab stop
+    Tab,
+    Space,
+}
+
+/// Walk an office XML part and collect its text. `text_elements` hold
+/// character data; `mark` maps element local names to the separator emitted
+/// when the element ends (or for empty elements such as `<w:tab/>`).
+/// Paragraph breaks inside a table cell become spaces so each table row
+/// stays on one line.
+fn xml_text(
+    xml: &[u8],
+    text_elements: &[&[u8]],
+    mark: impl Fn(&[u8]) -> Option<Mark>,
+) -> Result<String> {
+    let mut reader = XmlReader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut out = String::new();
+    let mut in_text = 0usize;
+    let mut in_cell = 0usize;
+
+    let push = |out: &mut String, m: Mark, in_cell: bool| {
+        if m != Mark::Space {
+            out.truncate(out.trim_end_matches(' ').len());
+        }
+        out.push(match m {
+            Mark::Newline if in_cell => ' ',
+            Mark::Newline => '\n',
+            Mark::Tab => '\t',
+            Mark::Space => ' ',
+        });
+    };
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = e.local_name();
+                if text_elements.contains(&name.as_ref()) {
+                    in_text += 1;
+                }
+                if mark(name.as_ref()) == Some(Mark::Tab) {
+                    in_cell += 1;
+                }
+            }
+            Event::End(e) => {
+                let name = e.local_name();
+                if text_elements.contains(&name.as_ref()) {
+                    in_text = in_text.saturating_sub(1);
+                }
+                if let Some(m) = mark(name.as_ref()) {
+                    if m == Mark::Tab {
+                        in_cell = in_cell.saturating_sub(1);
+                    }
+                    push(&mut out, m, in_cell > 0);
+                }
+            }
+            Event::Empty(e) => {
+                if let Some(m) = mark(e.local_name().as_ref()) {
+                    push(&mut out, m, in_cell > 0);
+                }
+            }
+            Event::Text(t) if in_text > 0 => out.push_str(&t.decode()?),
+            Event::GeneralRef(r) if in_text > 0 => {
+                if let Some(c) = r.resolve_char_ref()? {
+                    out.push(c);
+                } else if let Some(s) = resolve_predefined_entity(&r.decode()?) {
+                    out.push_str(s);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(out)
+}
+
+/// `word/document.xml`: runs in `<w:t>`, paragraphs `<w:p>`, table cells `<w:tc>`.
+fn docx_text(xml: &[u8]) -> Result<String> {
+    xml_text(xml, &[b"t"], |name| match name {
+        b"p" | b"br" | b"cr" | b"tr" => Some(Mark::Newline),
+        b"tab" | b"tc" => Some(Mark::Tab),
+        _ => None,
+    })
+}
+
+/// `content.xml` of an ODF text document: `<text:p>`/`<text:h>` hold the
+/// text directly, `<text:s/>` is a space, table cells and rows as in DOCX.
+fn odt_text(xml: &[u8]) -> Result<String> {
+    xml_text(xml, &[b"p", b"h", b"span", b"a"], |name| match name {
+        b"p" | b"h" | b"line-break" | b"table-row" => Some(Mark::Newline),
+        b"tab" | b"table-cell" => Some(Mark::Tab),
+        b"s" => Some(Mark::Space),
+        _ => None,
+    })
+}
+
+/// One `## <sheet>` section per worksheet, one line per row.
+fn xlsx_text(bytes: &[u8]) -> Result<String> {
+    let mut workbook = Xlsx::new(Cursor::new(bytes)).context("Not an XLSX workbook")?;
+    let mut out = String::new();
+    for name in workbook.sheet_names() {
+        let range = workbook
+            .worksheet_range(&name)
+            .with_context(|| format!("Cannot read sheet `{name}`"))?;
+        out.push_str(&format!("## {name}\n"));
+        for row in range.rows() {
+            let cells: Vec<String> = row.iter().map(|c| c.to_string()).collect();
+            out.push_str(cells.join("\t").trim_end());
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// Rows with tab-separated fields; the delimiter (`,` or `;`) is guessed
+/// from the first line.
+fn csv_text(bytes: &[u8]) -> Result<String> {
+    let first_line = bytes.split(|b| *b == b'\n').next().unwrap_or_default();
+    let count = |d: u8| first_line.iter().filter(|b| **b == d).count();
+    let delimiter = if count(b';') > count(b',') { b';' } else { b',' };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(bytes);
+    let mut out = String::new();
+    for record in reader.records() {
+        let record = record.context("Malformed CSV")?;
+        out.push_str(&record.iter().collect::<Vec<_>>().join("\t"));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Names of the files inside a ZIP archive, one per line.
+fn zip_listing(bytes: &[u8]) -> Result<String> {
+    let archive = ZipArchive::new(Cursor::new(bytes)).context("Not a ZIP archive")?;
+    Ok(archive.file_names().collect::<Vec<_>>().join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    fn zip_of(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn limits() -> ArchiveLimits {
+        ArchiveLimits::new(None, None, None)
+    }
+
+    #[test]
+    fn extracts_docx_paragraphs_and_tables() {
+        let docx = zip_of(&[(
+            "word/document.xml",
+            r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+                <w:p><w:r><w:t>Project </w:t></w:r><w:r><w:t>ACME &amp; Co</w:t></w:r></w:p>
+                <w:tbl><w:tr><w:tc><w:p><w:r><w:t>Name</w:t></w:r></w:p></w:tc>
+                <w:tc><w:p><w:r><w:t>Country</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+            </w:body></w:document>"#,
+        )]);
+        let text = extract_text(DocumentType::Docx, &docx, &limits()).unwrap();
+        assert_eq!(text, "Project ACME & Co\nName\tCountry");
+    }
+
+    #[test]
+    fn extracts_odt_text() {
+        let odt = zip_of(&[(
+            "content.xml",
+            r#"<office:document-content xmlns:office="o" xmlns:text="t"><office:body><office:text>
+                <text:h>Title</text:h><text:p>Hello<text:s/>world</text:p>
+            </office:text></office:body></office:document-content>"#,
+        )]);
+        assert_eq!(extract_text(DocumentType::Odt, &odt, &limits()).unwrap(), "Title\nHello world");
+    }
+
+    #[test]
+    fn extracts_xlsx_sheets() {
+        let xlsx = zip_of(&[
+            (
+                "[Content_Types].xml",
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+                <Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>
+                <Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>
+                </Types>"#,
+            ),
+            (
+                "_rels/.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+                <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>
+                </Relationships>"#,
+            ),
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
+                <sheets><sheet name="Budget" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+                <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>
+                </Relationships>"#,
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>
+                <row r="1"><c r="A1" t="inlineStr"><is><t>Partner</t></is></c><c r="B1" t="inlineStr"><is><t>Cost</t></is></c></row>
+                <row r="2"><c r="A2" t="inlineStr"><is><t>ACME</t></is></c><c r="B2"><v>1500</v></c></row>
+                </sheetData></worksheet>"#,
+            ),
+        ]);
+        let text = extract_text(DocumentType::Xlsx, &xlsx, &limits()).unwrap();
+        assert_eq!(text, "## Budget\nPartner\tCost\nACME\t1500");
+    }
+
+    #[test]
+    fn extracts_csv_and_zip_listing() {
+        let csv = b"name;country\nACME;\"France; Paris\"\n";
+        assert_eq!(
+            extract_text(DocumentType::Csv, csv, &limits()).unwrap(),
+            "name\tcountry\nACME\tFrance; Paris"
+        );
+
+        let archive = zip_of(&[("a.pdf", "%PDF-1.4"), ("docs/b.docx", "")]);
+        assert_eq!(extract_text(DocumentType::Zip, &archive, &limits()).unwrap(), "a.pdf\ndocs/b.docx");
+    }
+
+    #[test]
+    fn refuses_office_xml_beyond_the_unpack_limit() {
+        let xml = format!("<w:document><w:body>{}</w:body></w:document>", " ".repeat(4096));
+        let docx = zip_of(&[("word/document.xml", &xml)]);
+        let small = ArchiveLimits::new(None, Some(1024), None);
+        let err = extract_text(DocumentType::Docx, &docx, &small).unwrap_err();
+        assert!(err.to_string().contains("more than 1024 bytes"), "{err}");
+        assert!(extract_text(DocumentType::Docx, &docx, &limits()).is_ok());
+    }
+}
@@ -0,0 +1,411 @@
+//! Fetching pages for extraction. Online, every request passes the
+//! robots.txt check and the retry policy, and the page is recorded in the
+//! snapshot store and the run's WARC archive. Offline, pages come from the
+//! snapshot store, or from a WARC archive being replayed.
+//!
+//! The downloader shares the fetcher's client, retry policy, robots.txt
+//! rules and WARC archive; a replayed archive serves its downloads too. In a
+//! fixture run every request goes to the fixture server, while pages keep
+//! the URLs of the site they stand in for.
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use reqwest::{header::CONTENT_TYPE, Client, Response};
+use std::sync::Arc;
+use url::Url;
+
+use crate::{
+    charset,
+    fixtures::FixtureRoute,
+    retry::{send_with_retry, RetryPolicy},
+    robots::Robots,
+    snapshot::{Page, SnapshotStore},
+    warc::{Exchange, Payload, WarcArchive, WarcWriter},
+};
+
+#[derive(Clone)]
+pub struct PageFetcher {
+    client: Client,
+    retry: RetryPolicy,
+    robots: Arc<Robots>,
+    snapshots: Option<SnapshotStore>,
+    warc: Option<Arc<WarcWriter>>,
+    replay: Option<Arc<WarcArchive>>,
+    fixtures: Option<FixtureRoute>,
+    offline: bool,
+}
+
+impl PageFetcher {
+    pub fn new(client: Client, retry: RetryPolicy, robots: Arc<Robots>) -> Self {
+        Self {
+            client,
+            retry,
+            robots,
+            snapshots: None,
+            warc: None,
+            replay: None,
+            fixtures: None,
+            offline: false,
+        }
+    }
+
+    /// Record fetched pages in `store` or, when `offline`, read them from it
+    /// instead of the network.
+    pub fn with_snapshots(mut self, store: SnapshotStore, offline: bool) -> Self {
+        self.snapshots = Some(store);
+        self.offline = offline;
+        self
+    }
+
+    /// Record every request and response in `writer`.
+    pub fn with_warc(mut self, writer: Arc<WarcWriter>) -> Self {
+        self.warc = Some(writer);
+        self
+    }
+
+    /// Go offline and serve pages and downloads from `archive`.
+    pub fn replaying(mut self, archive: WarcArchive) -> Self {
+        self.replay = Some(Arc::new(archive));
+        self.offline = true;
+        self
+    }
+
+    /// Send every request to the fixture server of `route`.
+    pub fn with_fixtures(mut self, route: FixtureRoute) -> Self {
+        self.fixtures = Some(route);
+        self
+    }
+
+    /// No robots.txt, no snapshots.
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self::new(Client::new(), RetryPolicy::default(), Arc::new(Robots::disabled()))
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn warc(&self) -> Option<&WarcWriter> {
+        self.warc.as_deref()
+    }
+
+    /// The archive being replayed, if any.
+    pub fn replay(&self) -> Option<&WarcArchive> {
+        self.replay.as_deref()
+    }
+
+    /// Check `url` against robots.txt and wait for the host's crawl delay;
+    /// nothing to wait for offline.
+    pub async fn admit(&self, url: &Url) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        self.robots.admit(&self.target(url)?).await
+    }
+
+    /// Where a request for `url` goes: the fixture server in a fixture run.
+    pub fn target(&self, url: &Url) -> Result<Url> {
+        match &self.fixtures {
+            Some(route) => route.target(url),
+            None => Ok(url.clone()),
+        }
+    }
+
+    /// The URL a response came from, as the pages should know it.
+    fn original(&self, url: &Url) -> Url {
+        match &self.fixtures {
+            Some(route) => route.original(url),
+            None => url.clone(),
+        }
+    }
+
+    /// Headers of `url`, from a HEAD request or the replayed archive. The
+    /// page has an empty body.
+    pub async fn head(&self, url: &Url) -> Result<Page> {
+        if self.offline {
+            return self
+                .replay
+                .as_ref()
+                .and_then(|archive| archive.head(url))
+                .with_context(|| format!("No recorded HEAD response for {url}"));
+        }
+        let target = self.target(url)?;
+        self.robots.admit(&target).await?;
+        let response = send_with_retry(&self.retry, || self.client.head(target.clone())).await?;
+        let exchange = self.warc.is_some().then(|| Exchange {
+            method: "HEAD",
+            ..Exchange::new(&target, Vec::new(), &response)
+        });
+        let final_url = self.original(response.url());
+        let page = read_page(url, final_url, response, None).await?;
+        if let (Some(warc), Some(exchange)) = (&self.warc, &exchange) {
+            warc.record(exchange, Payload::Bytes(b""))
+                .with_context(|| format!("Cannot archive {url} in {}", warc.path().display()))?;
+        }
+        Ok(page)
+    }
+
+    /// The page at `url`, whatever its type.
+    pub async fn fetch(&self, url: &Url) -> Result<Page> {
+        let page = self.fetch_page(url, false, None).await?;
+        Ok(page.expect("only HTML-only fetches skip pages"))
+    }
+
+    /// The page at `url`, whatever its type, failing once its body exceeds
+    /// `max_bytes`.
+    pub async fn fetch_at_most(&self, url: &Url, max_bytes: u64) -> Result<Page> {
+        let page = self.fetch_page(url, false, Some(max_bytes)).await?;
+        Ok(page.expect("only HTML-only fetches skip pages"))
+    }
+
+    /// The page at `url` if it is HTML; other responses are not read.
+    pub async fn fetch_html(&self, url: &Url) -> Result<Option<Page>> {
+        self.fetch_page(url, true, None).await
+    }
+
+    async fn fetch_page(&self, url: &Url, html_only: bool, max_bytes: Option<u64>) -> Result<Option<Page>> {
+        if self.offline {
+            let page = match &self.replay {
+                Some(archive) => archive
+                    .get(url)
+                    .with_context(|| format!("{url} is not in the replayed WARC archive"))?,
+                None => {
+                    let store = self.snapshots.as_ref().context("Offline mode needs the snapshot store")?;
+                    let Some(page) = store.load(url)? else {
+                        bail!("No snapshot of {url}; run once online to record it");
+                    };
+                    page
+                }
+            };
+            if let Some(max) = max_bytes.filter(|&max| page.body.len() as u64 > max) {
+                bail!("{url} is larger than {max} bytes");
+            }
+            return Ok((!html_only || page.is_html()).then_some(page));
+        }
+
+        let target = self.target(url)?;
+        self.robots.admit(&target).await?;
+        let response = send_with_retry(&self.retry, || self.client.get(target.clone())).await?;
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_none_or(|ct| ct.to_ascii_lowercase().contains("html"));
+        if html_only && !is_html {
+            return Ok(None);
+        }
+        let exchange = self.warc.is_some().then(|| Exchange::new(&target, Vec::new(), &response));
+        let final_url = self.original(response.url());
+        let page = read_page(url, final_url, response, max_bytes).await?;
+        if let (Some(warc), Some(exchange)) = (&self.warc, &exchange) {
+            warc.record(exchange, Payload::Bytes(&page.body))
+                .with_context(|| format!("Cannot archive {url} in {}", warc.path().display()))?;
+        }
+        if let Some(store) = &self.snapshots {
+            if let Err(e) = store.save(&page) {
+                println!("Warning: cannot store a snapshot of {url}: {e:#}");
+            }
+        }
+        Ok(Some(page))
+    }
+}
+
+/// Read the response for `url`, giving up once the body exceeds `max_bytes`.
+async fn read_page(url: &Url, final_url: Url, mut response: Response, max_bytes: Option<u64>) -> Result<Page> {
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(k, v)| (k.as_str().to_owned(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+        .collect();
+    let too_large = |len: u64| max_bytes.filter(|&max| len > max);
+    if let Some(max) = response.content_length().and_then(too_large) {
+        bail!("{url} is larger than {max} bytes");
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.with_context(|| format!("Cannot read {url}"))? {
+        body.extend_from_slice(&chunk);
+        if let Some(max) = too_large(body.len() as u64) {
+            bail!("{url} is larger than {max} bytes");
+        }
+    }
+    let mut page = Page {
+        url: url.to_string(),
+        final_url: final_url.to_string(),
+        status,
+        headers,
+        fetched_at: Utc::now(),
+        charset: None,
+        body,
+    };
+    page.charset = Some(charset::detect(page.header("content-type"), &page.body).name().to_owned());
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{DownloadConfig, HttpConfig, SnapshotConfig},
+        downloader::download_pdfs,
+        manifest::Manifest,
+        test_server::{serve, TestResponse},
+    };
+    use crate::warc::read_records;
+    use std::{
+        collections::HashMap,
+        path::Path,
+        sync::Mutex,
+    };
+
+    #[tokio::test]
+    async fn offline_mode_replays_snapshots() {
+        let dir = "tmp_fetch_snapshots";
+        let store = || {
+            SnapshotStore::new(&SnapshotConfig {
+                dir: Some(dir.into()),
+                ..Default::default()
+            })
+        };
+        let base = serve(|_| TestResponse::new(200, "<p>live</p>").header("Content-Type", "text/html")).await;
+        let url = Url::parse(&format!("{base}/page")).unwrap();
+
+        let online = PageFetcher::for_tests().with_snapshots(store(), false);
+        assert_eq!(online.fetch(&url).await.unwrap().text(), "<p>live</p>");
+
+        let offline = PageFetcher::for_tests().with_snapshots(store(), true);
+        let page = offline.fetch_html(&url).await.unwrap().unwrap();
+        assert_eq!((page.status, page.text().as_str()), (200, "<p>live</p>"));
+        assert_eq!(page.header("content-type"), Some("text/html"));
+        let missing = Url::parse(&format!("{base}/other")).unwrap();
+        assert!(offline.fetch(&missing).await.unwrap_err().to_string().contains("No snapshot"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn decodes_pages_in_their_declared_charset() {
+        let dir = "tmp_fetch_charset";
+        let store = || {
+            SnapshotStore::new(&SnapshotConfig {
+                dir: Some(dir.into()),
+                ..Default::default()
+            })
+        };
+        let base = serve(|_| {
+            TestResponse::new(200, &b"<meta charset=\"iso-8859-1\"><td>Universit\xe9 de Li\xe8ge</td>"[..])
+                .header("Content-Type", "text/html")
+        })
+        .await;
+        let url = Url::parse(&format!("{base}/members")).unwrap();
+
+        let page = PageFetcher::for_tests().with_snapshots(store(), false).fetch(&url).await.unwrap();
+        assert!(page.text().ends_with("<td>Université de Liège</td>"));
+        assert_eq!(page.charset.as_deref(), Some("windows-1252"));
+
+        let stored = store().load(&url).unwrap().unwrap();
+        assert_eq!(stored.charset.as_deref(), Some("windows-1252"));
+        assert_eq!(stored.text(), page.text());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn capped_fetches_refuse_larger_bodies() {
+        let base = serve(|_| TestResponse::new(200, vec![b'x'; 100])).await;
+        let url = Url::parse(&format!("{base}/sitemap.xml")).unwrap();
+        let fetcher = PageFetcher::for_tests();
+
+        assert_eq!(fetcher.fetch_at_most(&url, 100).await.unwrap().body.len(), 100);
+        let err = fetcher.fetch_at_most(&url, 99).await.unwrap_err();
+        assert!(err.to_string().contains("larger than 99 bytes"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn archives_pages_and_downloads_as_warc() {
+        let dir = "tmp_fetch_warc";
+        std::fs::create_dir_all(dir).unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let base = serve(move |req| {
+            log.lock().unwrap().push(req.headers.clone());
+            match req.path.as_str() {
+                "/robots.txt" => TestResponse::new(200, "User-agent: *\nDisallow: /private"),
+                "/doc.pdf" => TestResponse::new(200, "%PDF-1.4 doc").header("Content-Type", "application/pdf"),
+                _ => TestResponse::new(200, "<p>live</p>").header("Content-Type", "text/html"),
+            }
+        })
+        .await;
+        let page = Url::parse(&format!("{base}/page")).unwrap();
+        let doc = Url::parse(&format!("{base}/doc.pdf")).unwrap();
+        let path = Path::new("tmp_fetch_warc/run.warc.gz");
+
+        let http = HttpConfig::default();
+        let client = crate::http::build_client(&http, 10).unwrap();
+        let writer = Arc::new(WarcWriter::create(path, &http).unwrap());
+        let robots = Robots::new(client.clone(), RetryPolicy::default(), "test", false).recording(writer.clone());
+        let fetcher = PageFetcher::new(client, RetryPolicy::default(), Arc::new(robots)).with_warc(writer);
+        fetcher.fetch(&page).await.unwrap();
+        let mut manifest = Manifest::default();
+        let urls = [doc.clone()];
+        let report = download_pdfs(&fetcher, &DownloadConfig::default(), &mut manifest, &urls, dir)
+            .await
+            .unwrap();
+        assert_eq!(report.downloaded, 1);
+        fetcher.head(&doc).await.unwrap();
+        drop(fetcher);
+
+        // robots.txt, page, download and HEAD, each with the headers really sent
+        let requests: Vec<_> = read_records(path)
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.header("WARC-Type") == Some("request"))
+            .collect();
+        let sent_headers = received.lock().unwrap().clone();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(sent_headers.len(), 4);
+        for (record, sent) in requests.iter().zip(&sent_headers) {
+            let head = String::from_utf8_lossy(&record.block);
+            let recorded: HashMap<String, String> = head
+                .lines()
+                .skip(1)
+                .filter_map(|l| l.split_once(": "))
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect();
+            assert_eq!(&recorded, sent, "{head}");
+        }
+        assert!(requests[0].header("WARC-Target-URI").unwrap().ends_with("/robots.txt"));
+        assert!(requests[3].block.starts_with(b"HEAD /doc.pdf "));
+
+        let archive = WarcArchive::open(path).unwrap();
+        let replay = PageFetcher::for_tests().replaying(archive);
+        assert!(replay.is_offline());
+        assert_eq!(replay.fetch_html(&page).await.unwrap().unwrap().text(), "<p>live</p>");
+        assert_eq!(replay.fetch(&doc).await.unwrap().body, b"%PDF-1.4 doc");
+        let head = replay.head(&doc).await.unwrap();
+        assert_eq!((head.header("content-type"), head.body.len()), (Some("application/pdf"), 0));
+        let missing = Url::parse(&format!("{base}/other")).unwrap();
+        assert!(replay.fetch(&missing).await.is_err());
+
+        // the downloads are replayed too, without a request
+        let replayed_dir = "tmp_fetch_warc/replayed";
+        let mut manifest = Manifest::default();
+        let report = download_pdfs(&replay, &DownloadConfig::default(), &mut manifest, &urls, replayed_dir)
+            .await
+            .unwrap();
+        assert_eq!(report.downloaded, 1);
+        assert_eq!(std::fs::read(Path::new(replayed_dir).join("doc.pdf")).unwrap(), b"%PDF-1.4 doc");
+        assert_eq!(received.lock().unwrap().len(), 4);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use percent_encoding::percent_decode_str;
+use std::path::Path;
+use url::Url;
+
+use crate::{manifest::sha256_hex, models::DocumentType};
+
+const MAX_FILENAME_LEN: usize = 150;
+
+/// File name announced by a `Content-Disposition` header, if any.
+/// The RFC 5987 `filename*` form wins over plain `filename`.
+pub fn content_disposition_filename(header: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+
+    for param in header.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded-value
+                let encoded = value.splitn(3, '\'').nth(2).unwrap_or(value);
+                extended = Some(percent_decode_str(encoded).decode_utf8_lossy().into_owned());
+            }
+            "filename" => plain = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    extended.or(plain).filter(|n| !n.trim().is_empty())
+}
+
+/// Last non-empty path segment of `url`, percent-decoded.
+pub fn url_filename(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .unwrap_or_else(|| "download".to_owned())
+}
+
+/// Make a server- or URL-provided name safe to use inside the output folder:
+/// drop directory parts, replace reserved and control characters, trim
+/// leading dots, cap the length and make sure the name carries the extension
+/// of `doc_type`.
+pub fn sanitize_filename(name: &str, doc_type: DocumentType) -> String {
+    // never trust directory components, whatever the separator
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+
+    let mut clean: String = base
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    clean = clean.trim().trim_start_matches('.').trim_end_matches(['.', ' ']).to_owned();
+
+    if clean.is_empty() {
+        clean = "download".to_owned();
+    }
+    let ext = format!(".{}", doc_type.extension());
+    if !clean.to_ascii_lowercase().ends_with(&ext) {
+        clean.push_str(&ext);
+    }
+    truncate_keeping_extension(&clean, MAX_FILENAME_LEN)
+}
+
+/// Append a short, URL-derived hash to `name` ("report.pdf" → "report-1a2b3c4d.pdf")
+/// so two URLs that want the same name both get a stable, distinct file.
+pub fn disambiguate(name: &str, url: &Url) -> String {
+    let hash = &sha256_hex(url.as_str().as_bytes())[..8];
+    let path = Path::new(name);
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    match path.extension() {
+        Some(ext) => format!("{stem}-{hash}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{hash}"),
+    }
+}
+
+fn truncate_keeping_extension(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_owned();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) => name.split_at(i),
+        None => (name, ""),
+    };
+    let mut cut = max.saturating_sub(ext.len());
+    while !stem.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    format!("{}{}", &stem[..cut], ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_content_disposition() {
+        assert_eq!(
+            content_disposition_filename(r#"attachment; filename="EDF 2024.pdf""#).as_deref(),
+            Some("EDF 2024.pdf")
+        );
+        assert_eq!(
+            content_disposition_filename(
+                r#"attachment; filename="fallback.pdf"; filename*=UTF-8''Projet%20%C3%A9t%C3%A9.pdf"#
+            )
+            .as_deref(),
+            Some("Projet été.pdf")
+        );
+        assert_eq!(content_disposition_filename("inline"), None);
+        assert_eq!(content_disposition_filename(r#"attachment; filename="""#), None);
+    }
+
+    #[test]
+    fn sanitizes_unsafe_names() {
+        let pdf = DocumentType::Pdf;
+        assert_eq!(sanitize_filename("../../etc/passwd", pdf), "passwd.pdf");
+        assert_eq!(sanitize_filename(r"C:\temp\a<b>.pdf", pdf), "a_b_.pdf");
+        assert_eq!(sanitize_filename("...", pdf), "download.pdf");
+        assert_eq!(sanitize_filename("report.PDF", pdf), "report.PDF");
+        assert_eq!(sanitize_filename("table", DocumentType::Xlsx), "table.xlsx");
+
+        let long = format!("{}.pdf", "x".repeat(300));
+        let clean = sanitize_filename(&long, pdf);
+        assert_eq!(clean.len(), MAX_FILENAME_LEN);
+        assert!(clean.ends_with(".pdf"));
+    }
+
+    #[test]
+    fn url_filename_falls_back_to_download() {
+        let url = Url::parse("https://host/files/My%20File.pdf").unwrap();
+        assert_eq!(url_filename(&url), "My File.pdf");
+        assert_eq!(url_filename(&Url::parse("https://host/").unwrap()), "download");
+    }
+
+    #[test]
+    fn disambiguation_is_stable_and_distinct() {
+        let a = Url::parse("https://host/a/download").unwrap();
+        let b = Url::parse("https://host/b/download").unwrap();
+        let name_a = disambiguate("download.pdf", &a);
+        assert_eq!(name_a, disambiguate("download.pdf", &a));
+        assert_ne!(name_a, disambiguate("download.pdf", &b));
+        assert!(name_a.starts_with("download-") && name_a.ends_with(".pdf"));
+    }
+}
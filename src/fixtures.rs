@@ -0,0 +1,222 @@
+//! Stand-in web server for fixture runs: `--fixtures <dir>` serves pages and
+//! documents from a directory on a local port, so the whole pipeline can run
+//! without the network. The fetcher sends every request for the configured
+//! site's origin there, whether it comes from `url`, an absolute link, a
+//! pagination template, a sitemap or a crawl, and refuses requests to any
+//! other origin.
+//!
+//! A request path maps to the file with the same path below the directory,
+//! or to `index.html` for a directory. With a query string the file
+//! `<file>?<query>` wins if it exists, so the second page of `/projects/`
+//! can live in `projects/index.html?page=2`. The `Content-Type` follows the
+//! file extension.
+
+use anyhow::{bail, Context, Result};
+use percent_encoding::percent_decode_str;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use url::Url;
+
+/// Longest request head accepted
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Serve `root` on an ephemeral local port and return the base URL.
+pub async fn serve_dir(root: &Path) -> Result<Url> {
+    let root = Arc::new(
+        root.canonicalize()
+            .with_context(|| format!("Cannot open fixture directory {}", root.display()))?,
+    );
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Cannot start the fixture server")?;
+    let base = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let root = root.clone();
+            tokio::spawn(async move {
+                let _ = respond(stream, &root).await;
+            });
+        }
+    });
+    Ok(base)
+}
+
+/// Where the requests of a fixture run go.
+#[derive(Debug, Clone)]
+pub struct FixtureRoute {
+    /// Root of the site the fixtures stand in for
+    site: Url,
+    /// Root of the fixture server
+    server: Url,
+}
+
+impl FixtureRoute {
+    /// Send the requests for the origin of `site` to `server`.
+    pub fn new(site: &Url, server: Url) -> Result<Self> {
+        let site = Url::parse(&site.origin().ascii_serialization())
+            .with_context(|| format!("{site} has no origin to stand in for"))?;
+        Ok(Self { site, server })
+    }
+
+    /// The fixture server's URL for `url`. Fails for URLs outside the site,
+    /// which a fixture run must not fetch.
+    pub fn target(&self, url: &Url) -> Result<Url> {
+        if url.origin() == self.server.origin() {
+            return Ok(url.clone());
+        }
+        if url.origin() != self.site.origin() {
+            bail!("{url} is outside the fixture site {}", self.site);
+        }
+        rebase(url, &self.server)
+    }
+
+    /// `url` on the site again, for a URL the fixture server answered from.
+    pub fn original(&self, url: &Url) -> Url {
+        if url.origin() != self.server.origin() {
+            return url.clone();
+        }
+        rebase(url, &self.site).unwrap_or_else(|_| url.clone())
+    }
+}
+
+/// `url` with its scheme, host and port replaced by those of `base`.
+fn rebase(url: &Url, base: &Url) -> Result<Url> {
+    let mut target = url.path().to_owned();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    Ok(base.join(&target)?)
+}
+
+/// File below `root` for the request target `path?query`; `None` for
+/// targets that do not exist or would leave `root`.
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    let mut file = root.to_path_buf();
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        file.push(segment.as_ref());
+    }
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    if let Some(query) = query {
+        let mut name = file.file_name()?.to_os_string();
+        name.push(format!("?{query}"));
+        let with_query = file.with_file_name(name);
+        if with_query.is_file() {
+            return Some(with_query);
+        }
+    }
+    file.is_file().then_some(file)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let name = path.to_string_lossy();
+    // the query part of `index.html?page=2` is not an extension
+    let name = name.split('?').next().unwrap_or_default();
+    let extension = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html",
+        "xml" => "application/xml",
+        "gz" => "application/gzip",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "pdf" => "application/pdf",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "csv" => "text/csv",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Answer the single request on `stream`, then close it.
+async fn respond(mut stream: TcpStream, root: &Path) -> Result<()> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_HEAD {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        head.extend_from_slice(&chunk[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or("/");
+
+    let (status, content_type, body) = match (method, resolve(root, target)) {
+        ("GET" | "HEAD", Some(file)) => ("200 OK", content_type(&file), tokio::fs::read(&file).await?),
+        ("GET" | "HEAD", None) => ("404 Not Found", "text/plain", b"no such fixture".to_vec()),
+        _ => ("405 Method Not Allowed", "text/plain", Vec::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(&body).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[tokio::test]
+    async fn serves_files_from_the_fixture_directory() {
+        let dir = Path::new("tmp_fixtures");
+        fs::create_dir_all(dir.join("projects")).unwrap();
+        fs::write(dir.join("projects/index.html"), "<p>page 1</p>").unwrap();
+        fs::write(dir.join("projects/index.html?page=2"), "<p>page 2</p>").unwrap();
+        fs::write(dir.join("projects/a b.pdf"), "%PDF-1.4").unwrap();
+
+        let base = serve_dir(dir).await.unwrap();
+        let site = Url::parse("https://example.org/projects/").unwrap();
+        let route = FixtureRoute::new(&site, base.clone()).unwrap();
+        let listing = Url::parse("https://example.org/projects/?page=2").unwrap();
+        let url = route.target(&listing).unwrap();
+        assert_eq!(url.as_str(), format!("{base}projects/?page=2"));
+        assert_eq!(route.target(&url).unwrap(), url);
+        assert_eq!(route.original(&url), listing);
+        assert!(route.target(&Url::parse("https://example.com/projects/").unwrap()).is_err());
+        assert!(route.target(&Url::parse("http://example.org/projects/").unwrap()).is_err());
+
+        let get = |path: &str| {
+            let url = base.join(path).unwrap();
+            async move { reqwest::get(url).await.unwrap() }
+        };
+        let page = get("projects/").await;
+        assert_eq!(page.headers()["content-type"], "text/html");
+        assert_eq!(page.text().await.unwrap(), "<p>page 1</p>");
+        assert_eq!(get("projects/?page=2").await.text().await.unwrap(), "<p>page 2</p>");
+        assert_eq!(get("projects/?page=3").await.text().await.unwrap(), "<p>page 1</p>");
+        let pdf = get("projects/a%20b.pdf").await;
+        assert_eq!(pdf.headers()["content-type"], "application/pdf");
+        assert_eq!(get("robots.txt").await.status(), 404);
+        assert!(resolve(&dir.canonicalize().unwrap(), "/projects/%2e%2e/%2e%2e/Cargo.toml").is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, USER_AGENT},
+    redirect::Policy,
+    Client,
+};
+use std::time::Duration;
+
+use crate::config::HttpConfig;
+
+/// User agent sent when `[http].user_agent` is not configured.
+pub const DEFAULT_USER_AGENT: &str = concat!("rust-webscraper/", env!("CARGO_PKG_VERSION"));
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Headers sent with every request: the user agent, the `Accept: */*`
+/// reqwest would add anyway, and `[http].headers`, which override both.
+pub fn default_headers(http: &HttpConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let user_agent = http.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_str(user_agent).with_context(|| format!("Invalid user agent `{user_agent}`"))?,
+    );
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    if let Some(extra) = &http.headers {
+        for (name, value) in extra {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name `{name}`"))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header `{name}`"))?;
+            headers.insert(name, value);
+        }
+    }
+    Ok(headers)
+}
+
+/// Build the single HTTP client shared by the page fetch and the downloader.
+/// `timeout` is the overall per-request timeout in seconds.
+pub fn build_client(http: &HttpConfig, timeout: u64) -> Result<Client> {
+    let redirect = match http.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS) {
+        0 => Policy::none(),
+        n => Policy::limited(n),
+    };
+
+    Client::builder()
+        .timeout(Duration::from_secs(timeout))
+        .connect_timeout(Duration::from_secs(
+            http.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+        ))
+        .default_headers(default_headers(http)?)
+        .redirect(redirect)
+        .build()
+        .context("Cannot build HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn builds_client_from_defaults() {
+        assert!(build_client(&HttpConfig::default(), 30).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_header_name() {
+        let http = HttpConfig {
+            headers: Some(HashMap::from([("bad header".into(), "x".into())])),
+            ..Default::default()
+        };
+        assert!(build_client(&http, 30).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Configurable rules deciding which extracted links point at documents.
+//!
+//! Rules come from the `[link_rules]` table and can be nested:
+//!
+//! ```toml
+//! [link_rules]
+//! any = [
+//!   { attribute = { name = "data-wt-preview", value = "pdf" } },
+//!   { all = [{ href_regex = "/files/" }, { text_regex = "(?i)download" }] },
+//!   { href_suffix = ".pdf" },
+//!   { type = "application/pdf" },
+//!   { css = "a.file-link" },
+//! ]
+//! ```
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+
+use crate::models::{ContentMode, DocumentType, ExtractedElement};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkRule {
+    /// `href` path ends with this suffix, case-insensitive, query ignored
+    HrefSuffix(String),
+    /// `href` matches this regular expression
+    HrefRegex(String),
+    /// `type` attribute equals this media type, e.g. `application/pdf`
+    Type(String),
+    /// Visible link text matches this regular expression
+    TextRegex(String),
+    /// The element matches this CSS selector in the page it came from, so
+    /// ancestors count (`.downloads a`). Without a snapshot of the page only
+    /// the element and its content are known, an element that cannot be
+    /// found in its snapshot never matches
+    Css(String),
+    /// Attribute is present and, if `value` is given, equals it case-insensitively
+    Attribute { name: String, value: Option<String> },
+    /// Every sub-rule matches
+    All(Vec<LinkRule>),
+    /// At least one sub-rule matches
+    Any(Vec<LinkRule>),
+}
+
+impl LinkRule {
+    /// The historic behaviour: `<pdf_attr>="pdf"`, as used by the EC Europa
+    /// component library (`data-wt-preview`), extended to `="docx"`, `="xlsx"`
+    /// and so on for the other enabled attachment types.
+    pub fn legacy(pdf_attr: &str, types: &[DocumentType]) -> Self {
+        let mut rules: Vec<LinkRule> = types
+            .iter()
+            .map(|t| LinkRule::Attribute {
+                name: pdf_attr.to_owned(),
+                value: Some(t.extension().to_owned()),
+            })
+            .collect();
+        if rules.len() == 1 {
+            rules.remove(0)
+        } else {
+            LinkRule::Any(rules)
+        }
+    }
+}
+
+/// A `LinkRule` with its regexes and selectors compiled once.
+#[derive(Debug)]
+pub enum LinkMatcher {
+    HrefSuffix(String),
+    HrefRegex(Regex),
+    Type(String),
+    TextRegex(Regex),
+    Css(Selector),
+    Attribute { name: String, value: Option<String> },
+    All(Vec<LinkMatcher>),
+    Any(Vec<LinkMatcher>),
+}
+
+impl LinkMatcher {
+    pub fn compile(rule: &LinkRule) -> Result<Self> {
+        Ok(match rule {
+            LinkRule::HrefSuffix(s) => LinkMatcher::HrefSuffix(s.to_lowercase()),
+            LinkRule::HrefRegex(r) => LinkMatcher::HrefRegex(
+                Regex::new(r).with_context(|| format!("Invalid href_regex `{r}`"))?,
+            ),
+            LinkRule::Type(t) => LinkMatcher::Type(t.trim().to_lowercase()),
+            LinkRule::TextRegex(r) => LinkMatcher::TextRegex(
+                Regex::new(r).with_context(|| format!("Invalid text_regex `{r}`"))?,
+            ),
+            LinkRule::Css(css) => match Selector::parse(css) {
+                Ok(sel) => LinkMatcher::Css(sel),
+                Err(e) => bail!("Invalid css rule `{css}`: {e:?}"),
+            },
+            LinkRule::Attribute { name, value } => LinkMatcher::Attribute {
+                name: name.clone(),
+                value: value.clone(),
+            },
+            LinkRule::All(rules) => {
+                LinkMatcher::All(rules.iter().map(Self::compile).collect::<Result<_>>()?)
+            }
+            LinkRule::Any(rules) => {
+                LinkMatcher::Any(rules.iter().map(Self::compile).collect::<Result<_>>()?)
+            }
+        })
+    }
+
+    /// Whether a `css` rule is involved, which is best checked in the
+    /// element's page.
+    pub fn needs_page(&self) -> bool {
+        match self {
+            LinkMatcher::Css(_) => true,
+            LinkMatcher::All(all) => all.iter().any(Self::needs_page),
+            LinkMatcher::Any(any) => any.iter().any(Self::needs_page),
+            _ => false,
+        }
+    }
+
+    /// Whether `el` matches; `in_page` is where it sits in the document it
+    /// was extracted from (see [`locate`]).
+    pub fn matches(&self, el: &ExtractedElement, in_page: InPage) -> bool {
+        let attr = |name: &str| el.attributes.as_ref().and_then(|a| a.get(name));
+        match self {
+            LinkMatcher::HrefSuffix(suffix) => attr("href").is_some_and(|href| {
+                let path = href.split(['?', '#']).next().unwrap_or_default();
+                path.to_lowercase().ends_with(suffix.as_str())
+            }),
+            LinkMatcher::HrefRegex(re) => attr("href").is_some_and(|href| re.is_match(href)),
+            LinkMatcher::Type(t) => attr("type").is_some_and(|v| v.trim().to_lowercase() == *t),
+            LinkMatcher::TextRegex(re) => re.is_match(&element_text(el)),
+            LinkMatcher::Css(sel) => match in_page {
+                InPage::Found(element) => sel.matches(&element),
+                InPage::Unknown => css_matches(sel, el),
+                InPage::Missing => false,
+            },
+            LinkMatcher::Attribute { name, value } => match (attr(name), value) {
+                (Some(_), None) => true,
+                (Some(v), Some(expected)) => v.eq_ignore_ascii_case(expected),
+                (None, _) => false,
+            },
+            LinkMatcher::All(all) => all.iter().all(|m| m.matches(el, in_page)),
+            LinkMatcher::Any(any) => any.iter().any(|m| m.matches(el, in_page)),
+        }
+    }
+}
+
+/// Visible text of an element, whitespace collapsed.
+fn element_text(el: &ExtractedElement) -> String {
+    let text = match el.content_mode {
+        ContentMode::Text | ContentMode::Markdown => el.content.clone(),
+        ContentMode::InnerHtml | ContentMode::OuterHtml => {
+            let fragment = Html::parse_fragment(&el.content);
+            fragment.root_element().text().collect()
+        }
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Where an extracted element sits in the page it came from.
+#[derive(Debug, Clone, Copy)]
+pub enum InPage<'a> {
+    /// There is no snapshot of the page, only the element itself is known
+    Unknown,
+    Found(ElementRef<'a>),
+    /// The page is known, but the element cannot be found in it
+    Missing,
+}
+
+/// `el` in `page`, the document it was extracted from, found by following
+/// its `dom_path`.
+pub fn locate<'a>(page: &'a Html, el: &ExtractedElement) -> InPage<'a> {
+    match el.dom_path.as_deref().and_then(|path| follow_dom_path(page, path)) {
+        Some(found) if found.value().name() == el.tag => InPage::Found(found),
+        _ => InPage::Missing,
+    }
+}
+
+/// The element a `context::dom_path` leads to, walked step by step rather
+/// than parsed as a CSS selector, so ids are compared as they are. An id
+/// shared by several elements leads nowhere.
+fn follow_dom_path<'a>(page: &'a Html, path: &str) -> Option<ElementRef<'a>> {
+    let mut steps = path.split(" > ");
+    let first = steps.next()?;
+    let start = match first.split_once('#') {
+        Some((name, id)) => {
+            let mut anchors = page
+                .root_element()
+                .descendants()
+                .filter_map(ElementRef::wrap)
+                .filter(|e| e.value().name() == name && e.value().id() == Some(id));
+            let anchor = anchors.next()?;
+            if anchors.next().is_some() {
+                return None;
+            }
+            anchor
+        }
+        None => Some(page.root_element()).filter(|root| root.value().name() == first)?,
+    };
+    steps.try_fold(start, |parent, step| {
+        let (name, nth) = match step.strip_suffix(')').and_then(|s| s.split_once(":nth-of-type(")) {
+            Some((name, n)) => (name, n.parse::<usize>().ok()?.checked_sub(1)?),
+            None => (step, 0),
+        };
+        parent
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|child| child.value().name() == name)
+            .nth(nth)
+    })
+}
+
+/// Rebuild the element from its tag, attributes and content (unless the
+/// content is the element already) and test the selector against it.
+fn css_matches(sel: &Selector, el: &ExtractedElement) -> bool {
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;");
+    let html = if el.content_mode == ContentMode::OuterHtml {
+        el.content.clone()
+    } else {
+        let mut html = format!("<{}", el.tag);
+        for (k, v) in el.attributes.iter().flatten() {
+            html.push_str(&format!(" {k}=\"{}\"", escape(v)));
+        }
+        let content = match el.content_mode {
+            ContentMode::InnerHtml => el.content.clone(),
+            _ => escape(&el.content),
+        };
+        html.push_str(&format!(">{content}</{}>", el.tag));
+        html
+    };
+
+    let fragment = Html::parse_fragment(&html);
+    fragment
+        .root_element()
+        .children()
+        .filter_map(scraper::ElementRef::wrap)
+        .next()
+        .is_some_and(|root| sel.matches(&root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn link(href: &str, content: &str, extra: &[(&str, &str)]) -> ExtractedElement {
+        let mut attributes: HashMap<String, String> =
+            extra.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        attributes.insert("href".into(), href.into());
+        ExtractedElement {
+            tag: "a".into(),
+            content: content.into(),
+            attributes: Some(attributes),
+            ..Default::default()
+        }
+    }
+
+    fn matcher(toml_rule: &str) -> LinkMatcher {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            rule: LinkRule,
+        }
+        let w: Wrapper = toml::from_str(&format!("rule = {toml_rule}")).unwrap();
+        LinkMatcher::compile(&w.rule).unwrap()
+    }
+
+    #[test]
+    fn css_rules_see_ancestors_in_the_source_page() {
+        let page = Html::parse_document(
+            r#"<div id="main"><div class="downloads"><a href="/a.pdf">A</a></div><p><a href="/b.pdf">B</a></p></div>"#,
+        );
+        let m = matcher(r#"{ css = ".downloads a" }"#);
+        assert!(m.needs_page());
+        let mut a = link("/a.pdf", "A", &[]);
+        a.dom_path = Some("div#main > div > a".into());
+        let mut b = link("/b.pdf", "B", &[]);
+        b.dom_path = Some("div#main > p > a".into());
+
+        assert!(m.matches(&a, locate(&page, &a)));
+        assert!(!m.matches(&b, locate(&page, &b)));
+        // without the page only the element itself is known
+        assert!(!m.matches(&a, InPage::Unknown));
+    }
+
+    #[test]
+    fn locates_elements_by_their_dom_path() {
+        let page = Html::parse_document(
+            r#"<div id="v2.files:main"><ul class="downloads"><li>x</li><li><a href="/a.pdf">A</a></li></ul></div>
+               <p id="twice"><a href="/b.pdf">B</a></p><p id="twice"><a href="/c.pdf">C</a></p>"#,
+        );
+        let m = matcher(r#"{ css = ".downloads a" }"#);
+        let mut a = link("/a.pdf", "A", &[]);
+        a.dom_path = Some("div#v2.files:main > ul > li:nth-of-type(2) > a".into());
+        assert!(matches!(locate(&page, &a), InPage::Found(el) if el.inner_html() == "A"));
+        assert!(m.matches(&a, locate(&page, &a)));
+
+        // a path that leads nowhere, or to more than one element, matches no css rule
+        a.dom_path = Some("div#v2.files:main > ul > li:nth-of-type(3) > a".into());
+        assert!(matches!(locate(&page, &a), InPage::Missing));
+        assert!(!m.matches(&a, locate(&page, &a)));
+        let mut b = link("/b.pdf", "B", &[]);
+        b.dom_path = Some("p#twice > a".into());
+        assert!(matches!(locate(&page, &b), InPage::Missing));
+        b.dom_path = Some("html > body > p:nth-of-type(1) > a".into());
+        assert!(matches!(locate(&page, &b), InPage::Found(el) if el.inner_html() == "B"));
+    }
+
+    #[test]
+    fn legacy_rule_matches_ec_preview_links() {
+        let m = LinkMatcher::compile(&LinkRule::legacy("data-wt-preview", &[DocumentType::Pdf]))
+            .unwrap();
+        assert!(m.matches(&link("/f", "x", &[("data-wt-preview", "PDF")]), InPage::Unknown));
+        assert!(!m.matches(&link("/f", "x", &[("data-wt-preview", "xlsx")]), InPage::Unknown));
+        assert!(!m.matches(&link("/f", "x", &[]), InPage::Unknown));
+
+        let types = [DocumentType::Pdf, DocumentType::Xlsx];
+        let m = LinkMatcher::compile(&LinkRule::legacy("data-wt-preview", &types)).unwrap();
+        assert!(m.matches(&link("/f", "x", &[("data-wt-preview", "xlsx")]), InPage::Unknown));
+        assert!(!m.matches(&link("/f", "x", &[("data-wt-preview", "docx")]), InPage::Unknown));
+    }
+
+    #[test]
+    fn simple_rules() {
+        assert!(matcher(r#"{ href_suffix = ".pdf" }"#).matches(&link("/a/B.PDF?x=1", "", &[]), InPage::Unknown));
+        assert!(!matcher(r#"{ href_suffix = ".pdf" }"#).matches(&link("/a.pdf.html", "", &[]), InPage::Unknown));
+        assert!(matcher(r#"{ href_regex = "/files/\\d+" }"#).matches(&link("/files/42", "", &[]), InPage::Unknown));
+        assert!(matcher(r#"{ type = "application/pdf" }"#)
+            .matches(&link("/x", "", &[("type", "Application/PDF")]), InPage::Unknown));
+        assert!(matcher(r#"{ text_regex = "(?i)^download factsheet$" }"#)
+            .matches(&link("/x", "\n  <span>Download</span>\n factsheet ", &[]), InPage::Unknown));
+        assert!(matcher(r#"{ attribute = { name = "download" } }"#)
+            .matches(&link("/x", "", &[("download", "")]), InPage::Unknown));
+    }
+
+    #[test]
+    fn css_rule_matches_element_and_content() {
+        let el = link("/x", "<span class=\"icon-pdf\"></span> Report", &[("class", "file-link big")]);
+        assert!(matcher(r#"{ css = "a.file-link" }"#).matches(&el, InPage::Unknown));
+        assert!(matcher(r#"{ css = "a.big" }"#).matches(&el, InPage::Unknown));
+        assert!(!matcher(r#"{ css = "a.other" }"#).matches(&el, InPage::Unknown));
+
+        let outer = ExtractedElement {
+            content: "<a class=\"file-link\" href=\"/x\">Report</a>".into(),
+            content_mode: ContentMode::OuterHtml,
+            ..link("/x", "", &[])
+        };
+        assert!(matcher(r#"{ css = "a.file-link" }"#).matches(&outer, InPage::Unknown));
+        assert!(matcher(r#"{ text_regex = "^Report$" }"#).matches(&outer, InPage::Unknown));
+    }
+
+    #[test]
+    fn rules_combine_with_and_or() {
+        let m = matcher(
+            r#"{ any = [
+                { attribute = { name = "data-wt-preview", value = "pdf" } },
+                { all = [{ href_regex = "/files/" }, { text_regex = "(?i)download" }] },
+            ] }"#,
+        );
+        assert!(m.matches(&link("/files/1", "Download", &[]), InPage::Unknown));
+        assert!(!m.matches(&link("/files/1", "Read more", &[]), InPage::Unknown));
+        assert!(m.matches(&link("/other", "", &[("data-wt-preview", "pdf")]), InPage::Unknown));
+    }
+
+    #[test]
+    fn invalid_regex_is_reported() {
+        assert!(LinkMatcher::compile(&LinkRule::HrefRegex("(".into())).is_err());
+    }
+}
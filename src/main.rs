@@ -1,35 +1,82 @@
+mod archive;
+mod charset;
 mod downloader;
+mod extractors;
+mod fetch;
 mod config;
+mod content;
+mod context;
+mod crawler;
+mod filename;
+mod fixtures;
+mod http;
+mod link_rules;
+mod manifest;
+mod retry;
+mod schema;
+mod robots;
+mod scrape;
+mod scrape_cache;
+mod selector;
+mod sitemap;
+mod snapshot;
+mod warc;
+mod sniff;
 mod cli_args;
 mod data;
 mod models;
 mod pdf_parser;
 mod pdf_processor;
 mod pdf_generator;
-
-use std::collections::HashMap;
+mod progress;
+#[cfg(test)]
+mod test_server;
 
 use config::load_config;
 use cli_args::CliArgs;
 use data::save_to_json;
 use clap::Parser;
-use anyhow::Result;
-use std::{path::Path};
-use models::ExtractedElement;
-use scraper::{Html, Selector};
-use downloader::{collect_pdf_links, download_pdfs};
+use anyhow::{Context, Result};
+use std::{path::Path, sync::Arc};
+use crawler::crawl;
+use fetch::PageFetcher;
+use fixtures::FixtureRoute;
+use snapshot::SnapshotStore;
+use warc::{WarcArchive, WarcWriter};
+use downloader::{
+    check_failure_threshold, collect_pdf_links, confirm_links_with_head, download_pdfs,
+};
+use manifest::Manifest;
+use archive::ArchiveLimits;
 use pdf_parser::parse_and_save;
+use retry::RetryPolicy;
+use robots::Robots;
+use schema::Schema;
+use scrape::{scrape_pages, Extractor};
+use scrape_cache::{CacheKey, ScrapeCache};
+use selector::ElementSelector;
+use sitemap::scrape_sitemap;
 use pdf_processor::process_pdf_texts;
 use pdf_generator::{generate_structured_summary, generate_json_summary};
 use models::PdfText;
+use url::Url;
 
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cfg = load_config("config.toml")?;
+    let mut cfg = load_config("config.toml")?;
     let cli_args = CliArgs::parse();
 
-    let json_path = "backup/extracted_elements.json";
+    // a fixture run keeps everything it stores apart: its stand-in pages and
+    // documents are filed under the real site's URLs, so a later real run
+    // must not compare against, skip for or replay them
+    let backup = if cli_args.fixtures.is_some() { "backup/fixtures" } else { "backup" };
+    if cli_args.fixtures.is_some() {
+        cfg.snapshots.dir = Some(format!("{backup}/snapshots"));
+        cfg.warc.dir = Some(format!("{backup}/warc"));
+        cfg.crawl.state_file = Some(format!("{backup}/crawl_state.json"));
+    }
+    let json_path = format!("{backup}/extracted_elements.json");
 
     let url = cli_args
     .url
@@ -52,66 +99,161 @@ async fn main() -> Result<()> {
 
     println!("Target URL: {}", url);
     println!("Request Timeout: {} seconds", timeout);
-    println!("Using selector: {}", selector_str);
-
-    if !Path::new(json_path).exists() {
-        println!("⏳ scraping (no cache yet) …");
-
-        let response = reqwest::get(&url).await?.text().await?;
-        let document = Html::parse_document(&response);
-        println!("Fetched document from {}", url);
-        let selector = Selector::parse(&selector_str).expect("Invalid CSS selector");
-
-        let mut extracted_elements: Vec<ExtractedElement> = Vec::new();
-
-        for element in document.select(&selector) {
-            let tag = element.value().name().to_string();
-            let content = element.inner_html();
+    let content_mode = cli_args.content_mode.or(cfg.content_mode).unwrap_or_default();
 
-            // Collect attributes
-            let attributes = element
-                .value()
-                .attrs()
-                .map(|(key, value)| (key.to_string(), value.to_string()))
-                .collect::<HashMap<_, _>>();
-
-            extracted_elements.push(ExtractedElement {
-                tag,
-                content,
-                attributes: if attributes.is_empty() {
-                    None
-                } else {
-                    Some(attributes)
-                },
-            });
-        }
+    println!("Using selector: {}", selector_str);
 
-        // Save scraped data (helper puts it inside backup/)
-        save_to_json(&extracted_elements, "extracted_elements.json")?;
-        println!("Saved {}", json_path);
+    let client = http::build_client(&cfg.http, timeout)?;
+    let retry_policy = RetryPolicy::from_config(&cfg.retry);
+    let online = cli_args.replay_warc.is_none() && !cli_args.offline;
+    let warc = if online && (cli_args.warc || cfg.warc.enabled.unwrap_or(false)) {
+        let writer = Arc::new(WarcWriter::for_run(&cfg.warc, &cfg.http)?);
+        println!("Archiving HTTP traffic to {}", writer.path().display());
+        Some(writer)
     } else {
-        println!("Cached scrape found at {}", json_path);
+        None
+    };
+    let mut robots = Robots::new(
+        client.clone(),
+        retry_policy.clone(),
+        cfg.http.user_agent.as_deref().unwrap_or(http::DEFAULT_USER_AGENT),
+        cli_args.ignore_robots,
+    );
+    if let Some(writer) = &warc {
+        robots = robots.recording(writer.clone());
+    }
+    let mut fetcher = PageFetcher::new(client.clone(), retry_policy.clone(), Arc::new(robots));
+    if cfg.snapshots.enabled.unwrap_or(true) || cli_args.offline {
+        fetcher = fetcher.with_snapshots(SnapshotStore::new(&cfg.snapshots), cli_args.offline);
+    }
+    if let Some(writer) = warc {
+        fetcher = fetcher.with_warc(writer);
+    }
+    if let Some(dir) = cli_args.fixtures.as_ref().filter(|_| !cli_args.offline) {
+        let site = Url::parse(&url).with_context(|| format!("Invalid URL `{url}`"))?;
+        let server = fixtures::serve_dir(Path::new(dir)).await?;
+        println!("Fixtures: serving {} at {} in place of {}", dir, server, site.origin().ascii_serialization());
+        println!("Fixtures: everything is stored in {backup}/");
+        fetcher = fetcher.with_fixtures(FixtureRoute::new(&site, server)?);
+    }
+    if let Some(path) = &cli_args.replay_warc {
+        fetcher = fetcher.replaying(WarcArchive::open(Path::new(path))?);
+        println!("Offline: pages and downloads come from {path}");
+    } else if cli_args.offline {
+        println!("Offline: pages come from the snapshot store");
     }
 
-    let pdf_urls = collect_pdf_links(json_path, &cfg)?;
-    download_pdfs(&pdf_urls, "backup").await?; // PDFs go to backup/
+    let cache = ScrapeCache::new(&cfg.cache);
+    if cli_args.clear_cache {
+        println!("Cleared {} cached scrape(s)", cache.clear()?);
+    }
+    let cache_key = CacheKey {
+        url: &url,
+        selector: &selector_str,
+        content_mode,
+        schema: cfg.schema.as_ref(),
+        pagination: &cfg.pagination,
+        crawl: &cfg.crawl,
+        sitemap: &cfg.sitemap,
+    };
+
+    let selector = ElementSelector::parse(&selector_str)?;
+    let schema = cfg.schema.as_ref().map(Schema::compile).transpose()?;
+    // offline runs exist to re-extract, so they never use the cache: archived
+    // pages must not pass for a fresh scrape in a later online run. Neither
+    // must the stand-in pages of a fixture run
+    let uncached = fetcher.is_offline() || cli_args.fixtures.is_some();
+    let cached = if cli_args.force_scrape || uncached {
+        None
+    } else {
+        cache.load(&cache_key)?
+    };
+    let extracted = match cached {
+        Some((extracted, scraped_at)) => {
+            println!("Using cached scrape {} from {}", cache_key.hash(), scraped_at);
+            extracted
+        }
+        None => {
+            println!("⏳ scraping …");
+            let extractor = Extractor::new(selector, content_mode, schema);
+            let extracted = if cfg.sitemap.url.is_some() {
+                scrape_sitemap(&fetcher, &url, &extractor, &cfg.sitemap).await?
+            } else if cfg.crawl.max_depth.is_some() {
+                crawl(&fetcher, &url, &extractor, &cfg.crawl).await?
+            } else {
+                // one page, or every page of a paginated listing
+                scrape_pages(&fetcher, &url, &extractor, &cfg.pagination).await?
+            };
+            if uncached {
+                extracted
+            } else {
+                cache.store(&cache_key, extracted)?
+            }
+        }
+    };
+
+    // Save the data for these inputs (helper puts it inside backup/), so the
+    // steps below never see another URL's or selector's elements
+    save_to_json(&extracted.elements, backup, "extracted_elements.json")?;
+    println!("Saved {}", json_path);
+    if cfg.schema.is_some() {
+        save_to_json(&extracted.records, backup, "records.json")?;
+        println!("Saved {} record(s) to {backup}/records.json", extracted.records.len());
+    }
 
-    parse_and_save("backup", std::path::Path::new("backup/pdf_text.json"))?;
+    let mut pdf_urls = collect_pdf_links(&json_path, &cfg)?;
+    let manifest_path = format!("{backup}/download_manifest.json");
+    let manifest_path = Path::new(&manifest_path);
+    let mut manifest = Manifest::load(manifest_path)?;
+    // offline runs only re-extract; documents already on disk are parsed below.
+    // A replayed WARC archive holds the documents too, so those are replayed
+    let report = if fetcher.is_offline() && fetcher.replay().is_none() {
+        println!("Offline: not downloading {} document link(s)", pdf_urls.len());
+        None
+    } else {
+        if cfg.pdf_head_fallback.unwrap_or(false) {
+            let confirmed = confirm_links_with_head(&fetcher, &json_path, &cfg, &pdf_urls).await?;
+            pdf_urls.extend(confirmed);
+        }
+        // PDFs go to backup/; failures are recorded instead of aborting the run
+        let report = download_pdfs(
+            &fetcher,
+            &cfg.download,
+            &mut manifest,
+            &pdf_urls,
+            backup,
+        )
+        .await?;
+        // persist change flags before parsing, so a crash there re-parses next run
+        manifest.save(manifest_path)?;
+        save_to_json(&report, backup, "download_report.json")?;
+        Some(report)
+    };
+
+    let limits = ArchiveLimits::from_config(&cfg.download);
+    parse_and_save(backup, Path::new(&format!("{backup}/pdf_text.json")), &manifest, &limits)?;
+    manifest.clear_changed();
+    manifest.save(manifest_path)?;
 
     // Check if we should process PDFs into structured format
     if cli_args.process_pdfs {
-        process_pdfs_to_summary()?;
+        process_pdfs_to_summary(backup)?;
+    }
+
+    // only now, so a few broken links never block parsing and processing
+    if let Some(report) = &report {
+        check_failure_threshold(report, &cfg.download)?;
     }
 
     Ok(())
 
 }
 
-/// Process existing PDF text data into structured summaries
-fn process_pdfs_to_summary() -> Result<()> {
-    let pdf_text_path = "backup/pdf_text.json";
+/// Process existing PDF text data in `backup` into structured summaries
+fn process_pdfs_to_summary(backup: &str) -> Result<()> {
+    let pdf_text_path = format!("{backup}/pdf_text.json");
     
-    if !Path::new(pdf_text_path).exists() {
+    if !Path::new(&pdf_text_path).exists() {
         println!("No PDF text file found at {}", pdf_text_path);
         return Ok(());
     }
@@ -119,7 +261,7 @@ fn process_pdfs_to_summary() -> Result<()> {
     println!("Processing PDF texts into structured summary...");
     
     // Load the existing PDF text data
-    let file = std::fs::File::open(pdf_text_path)?;
+    let file = std::fs::File::open(&pdf_text_path)?;
     let pdf_texts: Vec<PdfText> = serde_json::from_reader(file)?;
     
     // Process into structured format
@@ -129,12 +271,12 @@ fn process_pdfs_to_summary() -> Result<()> {
              summary.total_projects, pdf_texts.len());
     
     // Generate structured text summary
-    generate_structured_summary("backup/edf_summary.md", &summary)?;
-    println!("Generated structured summary: backup/edf_summary.md");
+    generate_structured_summary(&format!("{backup}/edf_summary.md"), &summary)?;
+    println!("Generated structured summary: {backup}/edf_summary.md");
     
     // Generate JSON for querying
-    generate_json_summary("backup/edf_summary.json", &summary)?;
-    println!("Generated JSON summary: backup/edf_summary.json");
+    generate_json_summary(&format!("{backup}/edf_summary.json"), &summary)?;
+    println!("Generated JSON summary: {backup}/edf_summary.json");
     
     Ok(())
 }
@@ -177,6 +319,7 @@ mod tests {
                 tag,
                 content,
                 attributes: if attributes.is_empty() { None } else { Some(attributes) },
+                ..Default::default()
             });
         }
 
@@ -204,6 +347,7 @@ mod tests {
             tag: "p".to_string(),
             content: "Paragraph 1".to_string(),
             attributes: Some(attributes),
+            ..Default::default()
         };
 
         let json = serde_json::to_string_pretty(&element).unwrap();
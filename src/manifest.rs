@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    path::Path,
+};
+
+/// What we know about one downloaded URL.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// File name inside the download directory
+    pub file: String,
+    pub sha256: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+    /// Content differs from the previously downloaded version and has not
+    /// been re-parsed yet
+    #[serde(default)]
+    pub changed: bool,
+    /// Set for files unpacked from a downloaded ZIP archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveMember>,
+}
+
+/// Where inside a downloaded archive a file came from.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ArchiveMember {
+    pub archive_url: String,
+    pub inner_path: String,
+}
+
+/// Provenance of a downloaded file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// URL the file (or the archive containing it) was downloaded from
+    pub url: String,
+    /// Path inside that archive
+    pub inner_path: Option<String>,
+}
+
+/// `download_manifest.json`: URL → downloaded file, keyed by URL so the
+/// output is sorted and diff-friendly. Files unpacked from an archive are
+/// keyed by the archive URL with the inner path as fragment.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct Manifest {
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Load the manifest, or start an empty one if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = fs::File::open(path)
+            .with_context(|| format!("Cannot open {}", path.display()))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("Cannot decode {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Cannot create {}", path.display()))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn get(&self, url: &str) -> Option<&ManifestEntry> {
+        self.entries.get(url)
+    }
+
+    pub fn insert(&mut self, url: String, entry: ManifestEntry) {
+        self.entries.insert(url, entry);
+    }
+
+    /// Files whose content changed since they were last parsed.
+    pub fn changed_files(&self) -> HashSet<String> {
+        self.entries
+            .values()
+            .filter(|e| e.changed)
+            .map(|e| e.file.clone())
+            .collect()
+    }
+
+    /// File name → source, for provenance in later stages.
+    pub fn sources(&self) -> HashMap<String, Source> {
+        self.entries
+            .iter()
+            .map(|(url, e)| {
+                let source = match &e.archive {
+                    Some(member) => Source {
+                        url: member.archive_url.clone(),
+                        inner_path: Some(member.inner_path.clone()),
+                    },
+                    None => Source {
+                        url: url.clone(),
+                        inner_path: None,
+                    },
+                };
+                (e.file.clone(), source)
+            })
+            .collect()
+    }
+
+    /// Forget change flags once downstream stages have consumed them.
+    pub fn clear_changed(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.changed = false;
+        }
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// SHA-256 of a file, read in chunks so large downloads never sit in memory.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Cannot open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, changed: bool) -> ManifestEntry {
+        ManifestEntry {
+            file: file.into(),
+            sha256: sha256_hex(file.as_bytes()),
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+            fetched_at: Utc::now(),
+            changed,
+            archive: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_and_tracks_changes() {
+        let path = Path::new("tmp_manifest.json");
+        let mut manifest = Manifest::default();
+        manifest.insert("https://host/a.pdf".into(), entry("a.pdf", true));
+        manifest.insert("https://host/b.pdf".into(), entry("b.pdf", false));
+        manifest.save(path).unwrap();
+
+        let mut loaded = Manifest::load(path).unwrap();
+        assert_eq!(loaded.get("https://host/a.pdf"), manifest.get("https://host/a.pdf"));
+        assert_eq!(loaded.changed_files(), HashSet::from(["a.pdf".to_string()]));
+
+        let mut inner = entry("c.pdf", false);
+        inner.archive = Some(ArchiveMember {
+            archive_url: "https://host/all.zip".into(),
+            inner_path: "docs/c.pdf".into(),
+        });
+        loaded.insert("https://host/all.zip#docs/c.pdf".into(), inner);
+        let sources = loaded.sources();
+        assert_eq!(sources["a.pdf"].url, "https://host/a.pdf");
+        assert_eq!(sources["c.pdf"].url, "https://host/all.zip");
+        assert_eq!(sources["c.pdf"].inner_path.as_deref(), Some("docs/c.pdf"));
+
+        loaded.clear_changed();
+        assert!(loaded.changed_files().is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_manifest_is_empty() {
+        let manifest = Manifest::load(Path::new("does_not_exist_manifest.json")).unwrap();
+        assert!(manifest.entries.is_empty());
+    }
+
+    #[test]
+    fn hashes_are_hex_sha256() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,17 +1,116 @@
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ExtractedElement {
     pub tag: String,
     pub content: String,
     pub attributes: Option<HashMap<String, String>>, // use a map or attributes
+    /// How `content` represents the element; older output is inner HTML
+    #[serde(default)]
+    pub content_mode: ContentMode,
+    /// Page the element was found on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// CSS-like path to the element, e.g. `div#results > ul > li:nth-of-type(3) > a`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dom_path: Option<String>,
+    /// Position among the selector's matches on its page, from 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// Text of the nearest heading above the element
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading: Option<String>,
+    /// `href` resolved against the page (and its `<base href>`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_url: Option<String>,
+}
+
+/// Representation of an element stored in `ExtractedElement.content`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum ContentMode {
+    /// The element's children as HTML
+    #[default]
+    InnerHtml,
+    /// The element itself as HTML
+    OuterHtml,
+    /// Visible text, one line per block element, whitespace collapsed
+    Text,
+    /// The element converted to Markdown
+    Markdown,
+}
+
+/// One container matched by the `[schema]`, with its named fields.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Record {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Kinds of attachment the pipeline can download and extract text from.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentType {
+    #[default]
+    Pdf,
+    Docx,
+    Xlsx,
+    Odt,
+    Csv,
+    Zip,
+}
+
+impl DocumentType {
+    pub const ALL: [DocumentType; 6] = [
+        DocumentType::Pdf,
+        DocumentType::Docx,
+        DocumentType::Xlsx,
+        DocumentType::Odt,
+        DocumentType::Csv,
+        DocumentType::Zip,
+    ];
+
+    /// File extension, also used as the name in config and output.
+    pub fn extension(self) -> &'static str {
+        match self {
+            DocumentType::Pdf => "pdf",
+            DocumentType::Docx => "docx",
+            DocumentType::Xlsx => "xlsx",
+            DocumentType::Odt => "odt",
+            DocumentType::Csv => "csv",
+            DocumentType::Zip => "zip",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.extension().eq_ignore_ascii_case(ext))
+    }
+
+    /// Type implied by the extension of a file name or URL path.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let ext = std::path::Path::new(path).extension()?.to_str()?;
+        Self::from_extension(ext)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PdfText {
     pub file: String,
     pub text: String,   // one string per page
+    /// URL the file was downloaded from, taken from the download manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// Path inside the archive at `source_url`, for files unpacked from a ZIP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_path: Option<String>,
+    /// Older `pdf_text.json` files only contain PDFs
+    #[serde(default)]
+    pub doc_type: DocumentType,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -44,3 +143,53 @@ pub struct EdfSummary {
     pub unique_participants: usize,
     pub projects: Vec<EdfProject>,
 }
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadOutcome {
+    Downloaded,
+    Skipped,
+    Failed,
+    /// The response was not a PDF and went to the quarantine folder
+    Quarantined,
+}
+
+/// One row of `download_report.json`: what happened to a single URL.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DownloadRecord {
+    pub url: String,
+    pub path: String,
+    pub outcome: DownloadOutcome,
+    pub http_status: Option<u16>,
+    pub bytes: Option<u64>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    /// Re-downloaded content differs from the previous version
+    #[serde(default)]
+    pub changed: bool,
+    /// Files extracted from this record's ZIP archive
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unpacked: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DownloadReport {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    #[serde(default)]
+    pub quarantined: usize,
+    pub records: Vec<DownloadRecord>,
+}
+
+impl DownloadReport {
+    /// Share of attempted URLs that failed or were quarantined, in `0.0..=1.0`.
+    pub fn failure_ratio(&self) -> f64 {
+        if self.records.is_empty() {
+            0.0
+        } else {
+            (self.failed + self.quarantined) as f64 / self.records.len() as f64
+        }
+    }
+}
@@ -1,6 +1,12 @@
 use anyhow::{Context, Result};
 use std::{collections::HashSet, fs, path::Path};
-use crate::models::{PdfText};
+use crate::{
+    archive::ArchiveLimits,
+    extractors::extract_text,
+    manifest::Manifest,
+    models::{DocumentType, PdfText},
+    progress::Progress,
+};
 
 
 // Read `output_json` if it exists and return a set of filenames already done.
@@ -16,43 +22,106 @@ fn already_parsed(output_json: &Path) -> Result<(Vec<PdfText>, HashSet<String>)>
     Ok((vec, set))
 }
 
-/// Extract plain text from every NEW PDF in `dir` and merge results into
-/// `output_json`.  Skips files that are already listed in the JSON.
-pub fn parse_and_save(dir: &str, output_json: &Path) -> Result<()> {
+/// Extract plain text from every NEW document (PDF, DOCX, XLSX, ODT, CSV,
+/// ZIP listing) in `dir` and merge results into `output_json`. Skips files
+/// that are already listed in the JSON, unless `manifest` flags them as
+/// changed (e.g. the server published a corrected version). Each entry
+/// records the URL it was downloaded from and, for files unpacked from a
+/// ZIP, the path inside the archive.
+/// A file that cannot be read or parsed is reported and left out, so the
+/// next run tries it again; `limits` bound what is unpacked from DOCX and
+/// ODT containers.
+pub fn parse_and_save(
+    dir: &str,
+    output_json: &Path,
+    manifest: &Manifest,
+    limits: &ArchiveLimits,
+) -> Result<()> {
     let (mut all_entries, done_set) = already_parsed(output_json)?;
+    let changed = manifest.changed_files();
+    let sources = manifest.sources();
 
-    let mut new_count = 0;
+    // provenance for entries parsed before the manifest knew about them
+    let mut backfilled = 0;
+    for entry in all_entries.iter_mut().filter(|e| e.source_url.is_none()) {
+        if let Some(source) = sources.get(&entry.file) {
+            entry.source_url = Some(source.url.clone());
+            entry.archive_path = source.inner_path.clone();
+            backfilled += 1;
+        }
+    }
+
+    let mut docs = Vec::new();
     for entry in fs::read_dir(dir)? {
         let path = entry?.path();
-        if path.extension().map(|e| e.eq_ignore_ascii_case("pdf")) != Some(true) {
-            continue; // not a PDF
+        let Some(doc_type) = DocumentType::from_path(&path.to_string_lossy()) else {
+            continue; // not a document
+        };
+        if path.is_file() {
+            docs.push((path, doc_type));
         }
+    }
+    docs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let progress = Progress::new("parsing", docs.len() as u64);
+    let mut new_count = 0;
+    let mut failed = 0;
+    for (path, doc_type) in docs {
         let fname = path.file_name().unwrap().to_string_lossy().into_owned();
-        if done_set.contains(&fname) {
-            println!("Skip {fname}, already parsed");
+        let reparse = changed.contains(&fname);
+        if done_set.contains(&fname) && !reparse {
+            progress.println(format!("Skip {fname}, already parsed"));
+            progress.file_done();
             continue; // idempotent: we parsed it before
         }
 
-        // ------------------------- extract text with pdf_extract ------------
-        let bytes = fs::read(&path)
-            .with_context(|| format!("Cannot read {:?}", path))?;
-        let raw = pdf_extract::extract_text_from_mem(&bytes)
-            .with_context(|| format!("Cannot parse {:?}", path))?;
-
-        // simple clean-up: drop leading/trailing whitespace, compress blanks
-        let text = raw
-            .lines()
-            .map(str::trim)
-            .filter(|l| !l.is_empty())
-            .collect::<Vec<_>>()
-            .join("\n");
+        if reparse {
+            progress.println(format!("Re-parsing {fname}, content changed"));
+            // the old text is outdated even if the new version fails below
+            all_entries.retain(|p| p.file != fname);
+        }
 
-        all_entries.push(PdfText { file: fname, text });
+        // ------------------------------------------------- extract text
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                progress.println(format!("Skip {fname}: cannot read it ({e})"));
+                progress.file_done();
+                failed += 1;
+                continue;
+            }
+        };
+        let mut bar = progress.start_file(&fname, Some(bytes.len() as u64));
+        let extracted = extract_text(doc_type, &bytes, limits);
+        bar.inc(bytes.len() as u64);
+        drop(bar);
+        progress.file_done();
+        let text = match extracted {
+            Ok(text) => text,
+            Err(e) => {
+                progress.println(format!("Skip {fname}: cannot parse it ({e:#})"));
+                failed += 1;
+                continue;
+            }
+        };
+        let source = sources.get(&fname);
+        all_entries.push(PdfText {
+            file: fname,
+            text,
+            source_url: source.map(|s| s.url.clone()),
+            archive_path: source.and_then(|s| s.inner_path.clone()),
+            doc_type,
+        });
         new_count += 1;
     }
 
-    if new_count == 0 {
-        println!("No new PDFs to parse");
+    progress.finish();
+
+    if failed > 0 {
+        println!("{failed} document(s) could not be parsed and are missing from {}", output_json.display());
+    }
+    if new_count == 0 && backfilled == 0 && failed == 0 {
+        println!("No new documents to parse");
         return Ok(()); // nothing changed, keep old file as-is
     }
 
@@ -60,6 +129,27 @@ pub fn parse_and_save(dir: &str, output_json: &Path) -> Result<()> {
     let file = fs::File::create(output_json)
         .with_context(|| format!("Cannot create {}", output_json.display()))?;
     serde_json::to_writer_pretty(file, &all_entries)?;
-    println!("Parsed {} new PDF(s), saved to {}", new_count, output_json.display());
+    println!("Parsed {} new document(s), saved to {}", new_count, output_json.display());
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_documents_that_fail_to_parse() {
+        let dir = "tmp_parse_skip";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{dir}/broken.docx"), "not a zip").unwrap();
+        fs::write(format!("{dir}/table.csv"), "name,country\nACME,BE\n").unwrap();
+        let output = Path::new(dir).join("pdf_text.json");
+
+        let limits = ArchiveLimits::new(None, None, None);
+        parse_and_save(dir, &output, &Manifest::default(), &limits).unwrap();
+        let (entries, _) = already_parsed(&output).unwrap();
+        let files: Vec<_> = entries.iter().map(|e| e.file.as_str()).collect();
+        assert_eq!(files, ["table.csv"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -344,11 +344,11 @@ fn parse_with_known_patterns(line: &str) -> Option<(String, String)> {
     
     // Sort by length (longest first) to match multi-word countries first
     let mut sorted_countries = known_countries.to_vec();
-    sorted_countries.sort_by(|a, b| b.len().cmp(&a.len()));
+    sorted_countries.sort_by_key(|c| std::cmp::Reverse(c.len()));
     
     for country in &sorted_countries {
-        if line.ends_with(country) {
-            let name_part = line[..line.len() - country.len()].trim();
+        if let Some(name_part) = line.strip_suffix(country) {
+            let name_part = name_part.trim();
             if !name_part.is_empty() && !name_part.contains("COUNTRY") {
                 return Some((name_part.to_string(), country.to_string()));
             }
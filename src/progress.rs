@@ -0,0 +1,217 @@
+//! Progress reporting for long-running stages (downloads, parsing).
+//! Draws progress bars on an interactive terminal and falls back to
+//! periodic log lines when stdout is not a TTY (cron, CI).
+
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    io::{stdout, IsTerminal},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Minimum gap between two log lines in non-interactive mode.
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Overall progress of one stage, shared by all of its workers.
+pub struct Progress {
+    label: &'static str,
+    total: u64,
+    done: AtomicU64,
+    started: Instant,
+    bars: Option<(MultiProgress, ProgressBar)>,
+    last_log: Mutex<Instant>,
+}
+
+impl Progress {
+    /// Bars if stdout is a terminal, log lines otherwise.
+    pub fn new(label: &'static str, total: u64) -> Arc<Self> {
+        Self::with_bars(label, total, stdout().is_terminal())
+    }
+
+    pub fn with_bars(label: &'static str, total: u64, bars: bool) -> Arc<Self> {
+        let bars = bars.then(|| {
+            let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
+            let overall = multi.add(ProgressBar::new(total));
+            overall.set_style(
+                ProgressStyle::with_template("{prefix:>10} [{bar:30}] {pos}/{len} files, ETA {eta}")
+                    .expect("valid template")
+                    .progress_chars("=> "),
+            );
+            overall.set_prefix(label);
+            (multi, overall)
+        });
+        Arc::new(Self {
+            label,
+            total,
+            done: AtomicU64::new(0),
+            started: Instant::now(),
+            bars,
+            // log the first update right away
+            last_log: Mutex::new(Instant::now() - LOG_INTERVAL),
+        })
+    }
+
+    /// Print a line without tearing the bars.
+    pub fn println(&self, msg: impl AsRef<str>) {
+        match &self.bars {
+            Some((multi, _)) => {
+                let _ = multi.println(msg);
+            }
+            None => println!("{}", msg.as_ref()),
+        }
+    }
+
+    /// Start byte-level tracking for one file; `len` may be unknown.
+    pub fn start_file(self: &Arc<Self>, name: &str, len: Option<u64>) -> FileProgress {
+        let bar = self.bars.as_ref().map(|(multi, _)| {
+            let bar = multi.add(ProgressBar::new(len.unwrap_or(0)));
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{prefix:>10} {wide_msg} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+                )
+                .expect("valid template"),
+            );
+            bar.set_message(name.to_owned());
+            bar
+        });
+        FileProgress {
+            progress: self.clone(),
+            name: name.to_owned(),
+            bar,
+            len,
+            pos: 0,
+            started: Instant::now(),
+            last_log: Instant::now(),
+        }
+    }
+
+    /// One more file finished, successfully or not.
+    pub fn file_done(&self) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        match &self.bars {
+            Some((_, overall)) => overall.set_position(done),
+            None => {
+                let mut last = self.last_log.lock().unwrap();
+                if done == self.total || last.elapsed() >= LOG_INTERVAL {
+                    *last = Instant::now();
+                    println!("[{}] {}", self.label, self.summary(done));
+                }
+            }
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some((_, overall)) = &self.bars {
+            overall.finish_and_clear();
+        }
+    }
+
+    fn summary(&self, done: u64) -> String {
+        let mut line = format!("{done}/{} files", self.total);
+        if let Some(eta) = eta(self.started.elapsed(), done, self.total) {
+            line.push_str(&format!(", ETA {}", HumanDuration(eta)));
+        }
+        line
+    }
+}
+
+/// Remaining time, extrapolated from the average time per finished unit.
+pub fn eta(elapsed: Duration, done: u64, total: u64) -> Option<Duration> {
+    if done == 0 || done >= total {
+        return None;
+    }
+    Some(elapsed.div_f64(done as f64).mul_f64((total - done) as f64))
+}
+
+/// Byte-level progress of a single file.
+pub struct FileProgress {
+    progress: Arc<Progress>,
+    name: String,
+    bar: Option<ProgressBar>,
+    len: Option<u64>,
+    pos: u64,
+    started: Instant,
+    last_log: Instant,
+}
+
+impl FileProgress {
+    pub fn set_length(&mut self, len: Option<u64>) {
+        self.len = len;
+        if let Some(bar) = &self.bar {
+            bar.set_length(len.unwrap_or(0));
+        }
+    }
+
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+        if let Some(bar) = &self.bar {
+            bar.set_position(pos);
+        }
+    }
+
+    pub fn inc(&mut self, bytes: u64) {
+        self.pos += bytes;
+        match &self.bar {
+            Some(bar) => bar.inc(bytes),
+            None if self.last_log.elapsed() >= LOG_INTERVAL => {
+                self.last_log = Instant::now();
+                self.progress.println(self.summary());
+            }
+            None => {}
+        }
+    }
+
+    fn summary(&self) -> String {
+        let secs = self.started.elapsed().as_secs_f64().max(0.001);
+        let rate = HumanBytes((self.pos as f64 / secs) as u64);
+        match self.len {
+            Some(len) => format!(
+                "  {}: {} / {} ({rate}/s)",
+                self.name,
+                HumanBytes(self.pos),
+                HumanBytes(len)
+            ),
+            None => format!("  {}: {} ({rate}/s)", self.name, HumanBytes(self.pos)),
+        }
+    }
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_extrapolates_average() {
+        assert_eq!(eta(Duration::from_secs(10), 2, 6), Some(Duration::from_secs(20)));
+        assert_eq!(eta(Duration::from_secs(10), 0, 6), None);
+        assert_eq!(eta(Duration::from_secs(10), 6, 6), None);
+    }
+
+    #[test]
+    fn log_mode_tracks_files_and_bytes() {
+        let progress = Progress::with_bars("test", 2, false);
+        let mut file = progress.start_file("a.pdf", None);
+        file.set_length(Some(10));
+        file.inc(4);
+        file.inc(6);
+        assert_eq!(file.pos, 10);
+        assert!(file.summary().contains("a.pdf"));
+        drop(file);
+
+        progress.file_done();
+        progress.file_done();
+        assert_eq!(progress.done.load(Ordering::SeqCst), 2);
+        progress.finish();
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+
+use crate::config::RetryConfig;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+const DEFAULT_BACKOFF_BASE_MS: u64 = 500;
+const DEFAULT_BACKOFF_CAP_MS: u64 = 30_000;
+const DEFAULT_RETRY_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+/// Resolved retry settings with defaults applied.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff_base: Duration,
+    pub backoff_cap: Duration,
+    pub retry_statuses: Vec<u16>,
+    pub respect_retry_after: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from_config(&RetryConfig::default())
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(cfg: &RetryConfig) -> Self {
+        Self {
+            max_attempts: cfg.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            backoff_base: Duration::from_millis(
+                cfg.backoff_base_ms.unwrap_or(DEFAULT_BACKOFF_BASE_MS),
+            ),
+            backoff_cap: Duration::from_millis(cfg.backoff_cap_ms.unwrap_or(DEFAULT_BACKOFF_CAP_MS)),
+            retry_statuses: cfg
+                .retry_statuses
+                .clone()
+                .unwrap_or_else(|| DEFAULT_RETRY_STATUSES.to_vec()),
+            respect_retry_after: cfg.respect_retry_after.unwrap_or(true),
+        }
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_statuses.contains(&status.as_u16())
+    }
+
+    /// Exponential backoff with "equal jitter": half of the capped delay is
+    /// fixed, the other half is random. `attempt` starts at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .backoff_base
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.backoff_cap);
+        let half = exp / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter_ms)
+    }
+
+    /// Delay before the next attempt; a `Retry-After` value wins over the
+    /// computed backoff but is still bounded by `backoff_cap`.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(d) if self.respect_retry_after => d.min(self.backoff_cap),
+            _ => self.backoff(attempt),
+        }
+    }
+}
+
+/// Parse a `Retry-After` header value: either delta-seconds or an HTTP-date.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delta = date.with_timezone(&Utc) - now;
+    Some(delta.to_std().unwrap_or(Duration::ZERO))
+}
+
+fn retry_after_of(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+}
+
+/// Send the request produced by `make` until it succeeds, a non-retryable
+/// error occurs or `policy.max_attempts` is exhausted.
+/// Non-success statuses are turned into errors, like `error_for_status`.
+pub async fn send_with_retry<F>(policy: &RetryPolicy, make: F) -> Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 1;
+    loop {
+        let last_try = attempt >= policy.max_attempts;
+        let wait = match make().send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(resp),
+            Ok(resp) if !last_try && policy.is_retryable_status(resp.status()) => {
+                let wait = policy.delay(attempt, retry_after_of(&resp));
+                println!(
+                    "{} answered {}, retry {}/{} in {:?}",
+                    resp.url(),
+                    resp.status(),
+                    attempt,
+                    policy.max_attempts - 1,
+                    wait
+                );
+                wait
+            }
+            Ok(resp) => {
+                let url = resp.url().clone();
+                return resp
+                    .error_for_status()
+                    .map_err(|e| anyhow!(e).context(format!("Request to {url} failed")));
+            }
+            Err(e) if !last_try && is_transient(&e) => {
+                let wait = policy.delay(attempt, None);
+                println!(
+                    "Request failed ({e}), retry {}/{} in {:?}",
+                    attempt,
+                    policy.max_attempts - 1,
+                    wait
+                );
+                wait
+            }
+            Err(e) => return Err(anyhow!(e).context(format!("Request failed after {attempt} attempt(s)"))),
+        };
+        tokio::time::sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::from_config(&RetryConfig {
+            backoff_base_ms: Some(100),
+            backoff_cap_ms: Some(1_000),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let p = policy();
+        for _ in 0..50 {
+            let first = p.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+
+            let third = p.backoff(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+
+            let late = p.backoff(20);
+            assert!(late >= Duration::from_millis(500) && late <= Duration::from_millis(1_000));
+        }
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_date() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // dates in the past mean "retry now"
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn retry_after_is_bounded_by_cap() {
+        let p = policy();
+        assert_eq!(p.delay(1, Some(Duration::from_secs(60))), Duration::from_millis(1_000));
+        assert_eq!(p.delay(1, Some(Duration::from_millis(10))), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn default_statuses_cover_transient_errors() {
+        let p = RetryPolicy::default();
+        assert!(p.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(p.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!p.is_retryable_status(StatusCode::NOT_FOUND));
+    }
+}
@@ -0,0 +1,401 @@
+//! robots.txt support (RFC 9309): rules are fetched once per host and
+//! cached, `Allow`/`Disallow` decide whether a URL may be fetched and
+//! `Crawl-delay` spaces out requests to the same host.
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use reqwest::{Client, StatusCode};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{Mutex, OnceCell},
+    time::Instant,
+};
+use url::Url;
+
+use crate::{
+    retry::{send_with_retry, RetryPolicy},
+    warc::{Exchange, Payload, WarcWriter},
+};
+
+/// Longest `Crawl-delay` we honour; anything above is a misconfiguration.
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Rule {
+    allow: bool,
+    /// Pattern length, for "longest match wins"
+    len: usize,
+    pattern: Regex,
+}
+
+/// One `User-agent` block: its agents and its `key: value` lines.
+type Group = (Vec<String>, Vec<(String, String)>);
+
+/// The rules of one robots.txt that apply to our user agent.
+#[derive(Debug, Default)]
+pub struct RobotsRules {
+    rules: Vec<Rule>,
+    disallow_all: bool,
+    pub crawl_delay: Option<Duration>,
+}
+
+impl RobotsRules {
+    /// Everything allowed, e.g. when robots.txt does not exist.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Nothing allowed, e.g. when robots.txt is unreachable (server error).
+    pub fn disallow_all() -> Self {
+        Self {
+            disallow_all: true,
+            ..Self::default()
+        }
+    }
+
+    /// Parse `text`, keeping the groups for `agent` (the product token, e.g.
+    /// `rust-webscraper`) or, if there are none, the `*` groups.
+    pub fn parse(text: &str, agent: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim().to_owned();
+            if key == "user-agent" {
+                if !in_agents {
+                    groups.push((Vec::new(), Vec::new()));
+                    in_agents = true;
+                }
+                groups.last_mut().unwrap().0.push(value.to_ascii_lowercase());
+            } else if let Some(group) = groups.last_mut() {
+                in_agents = false;
+                group.1.push((key, value));
+            }
+        }
+
+        let agent = agent.to_ascii_lowercase();
+        let ours: Vec<_> = groups.iter().filter(|(agents, _)| agents.contains(&agent)).collect();
+        let selected = if ours.is_empty() {
+            groups.iter().filter(|(agents, _)| agents.iter().any(|a| a == "*")).collect()
+        } else {
+            ours
+        };
+
+        let mut rules = Self::default();
+        for (key, value) in selected.into_iter().flat_map(|(_, lines)| lines) {
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => rules.rules.push(Rule {
+                    allow: key == "allow",
+                    len: value.len(),
+                    pattern: pattern_regex(value),
+                }),
+                "crawl-delay" => {
+                    rules.crawl_delay = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|s| s.is_finite() && *s >= 0.0)
+                        .map(|s| Duration::from_secs_f64(s).min(MAX_CRAWL_DELAY));
+                }
+                _ => {}
+            }
+        }
+        rules
+    }
+
+    /// Whether `url` may be fetched: the longest matching rule decides,
+    /// `Allow` wins a tie, no match means allowed.
+    pub fn allowed(&self, url: &Url) -> bool {
+        if url.path() == "/robots.txt" {
+            return true;
+        }
+        if self.disallow_all {
+            return false;
+        }
+        let target = match url.query() {
+            Some(q) => format!("{}?{q}", url.path()),
+            None => url.path().to_owned(),
+        };
+        self.rules
+            .iter()
+            .filter(|r| r.pattern.is_match(&target))
+            .max_by_key(|r| (r.len, r.allow))
+            .is_none_or(|r| r.allow)
+    }
+}
+
+/// `*` matches anything, a trailing `$` anchors the end, everything else is
+/// a literal path prefix.
+fn pattern_regex(pattern: &str) -> Regex {
+    let (body, anchored) = match pattern.strip_suffix('$') {
+        Some(body) => (body, true),
+        None => (pattern, false),
+    };
+    let mut re = String::from("^");
+    re.push_str(&body.split('*').map(regex::escape).collect::<Vec<_>>().join(".*"));
+    if anchored {
+        re.push('$');
+    }
+    Regex::new(&re).expect("escaped pattern is a valid regex")
+}
+
+/// Product token of a user agent: "rust-webscraper/0.1 (+url)" → "rust-webscraper".
+pub fn product_token(user_agent: &str) -> &str {
+    user_agent
+        .split(['/', ' '])
+        .next()
+        .unwrap_or(user_agent)
+}
+
+struct HostRobots {
+    rules: RobotsRules,
+    /// Why robots.txt could not be read, refusing the whole host
+    unreadable: Option<String>,
+    /// Earliest time the next request to this host may start
+    next_slot: Mutex<Instant>,
+}
+
+/// robots.txt rules per host, fetched on first use.
+pub struct Robots {
+    client: Client,
+    retry: RetryPolicy,
+    agent: String,
+    /// `--ignore-robots`: warn about disallowed URLs instead of refusing them
+    ignore: bool,
+    /// Skip robots.txt entirely (tests)
+    disabled: bool,
+    /// Archive of the run's HTTP traffic, robots.txt files included
+    warc: Option<Arc<WarcWriter>>,
+    /// Per origin, filled by the first request for it; a slow robots.txt
+    /// only holds up its own host
+    hosts: std::sync::Mutex<HashMap<String, Arc<OnceCell<Arc<HostRobots>>>>>,
+}
+
+impl Robots {
+    pub fn new(client: Client, retry: RetryPolicy, user_agent: &str, ignore: bool) -> Self {
+        Self {
+            client,
+            retry,
+            agent: product_token(user_agent).to_owned(),
+            ignore,
+            disabled: false,
+            warc: None,
+            hosts: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record every robots.txt fetch in `writer`.
+    pub fn recording(mut self, writer: Arc<WarcWriter>) -> Self {
+        self.warc = Some(writer);
+        self
+    }
+
+    /// No robots.txt lookups, no delays.
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        Self {
+            disabled: true,
+            ..Self::new(Client::new(), RetryPolicy::default(), "test", false)
+        }
+    }
+
+    /// Check `url` against its host's robots.txt and wait for the host's
+    /// crawl delay. Fails for disallowed URLs unless robots are ignored.
+    pub async fn admit(&self, url: &Url) -> Result<()> {
+        if self.disabled {
+            return Ok(());
+        }
+        let host = self.host(url).await;
+        if !host.rules.allowed(url) {
+            if !self.ignore {
+                if let Some(reason) = &host.unreadable {
+                    bail!(
+                        "{url} is refused because robots.txt of its host could not be read ({reason}) (use --ignore-robots to override)"
+                    );
+                }
+                bail!(
+                    "{url} is disallowed by robots.txt for user agent `{}` (use --ignore-robots to override)",
+                    self.agent
+                );
+            }
+            println!("Warning: fetching {url} although robots.txt disallows it (--ignore-robots)");
+        }
+        if let Some(delay) = host.rules.crawl_delay {
+            let mut next = host.next_slot.lock().await;
+            tokio::time::sleep_until(*next).await;
+            *next = Instant::now() + delay;
+        }
+        Ok(())
+    }
+
+    /// The rules of `url`'s origin, read by whichever request comes first
+    /// while the others for that origin wait.
+    async fn host(&self, url: &Url) -> Arc<HostRobots> {
+        let origin = url.origin().ascii_serialization();
+        let cell = self
+            .hosts
+            .lock()
+            .expect("robots.txt table poisoned")
+            .entry(origin.clone())
+            .or_default()
+            .clone();
+        let host = cell.get_or_init(|| async { Arc::new(self.fetch(&origin).await) }).await;
+        host.clone()
+    }
+
+    async fn fetch(&self, origin: &str) -> HostRobots {
+        let robots_url = format!("{origin}/robots.txt");
+        let (rules, unreadable) = match self.read(&robots_url).await {
+            Ok(text) => (RobotsRules::parse(&text, &self.agent), None),
+            Err(e) => {
+                let status = e.downcast_ref::<reqwest::Error>().and_then(|e| e.status());
+                match status {
+                    // no robots.txt (or not for us): everything is allowed
+                    Some(s) if s.is_client_error() && s != StatusCode::TOO_MANY_REQUESTS => {
+                        (RobotsRules::allow_all(), None)
+                    }
+                    _ if self.ignore => {
+                        println!("Warning: cannot read {robots_url} ({e:#}), fetching from {origin} anyway (--ignore-robots)");
+                        (RobotsRules::disallow_all(), Some(format!("{e:#}")))
+                    }
+                    _ => {
+                        println!(
+                            "Warning: cannot read {robots_url} ({e:#}); every page and download on {origin} fails for the rest of this run (use --ignore-robots to fetch them anyway)"
+                        );
+                        (RobotsRules::disallow_all(), Some(format!("{e:#}")))
+                    }
+                }
+            }
+        };
+        HostRobots {
+            rules,
+            unreadable,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Body of `robots_url`, archived when the run writes a WARC file.
+    async fn read(&self, robots_url: &str) -> Result<String> {
+        let url = Url::parse(robots_url)?;
+        let response = send_with_retry(&self.retry, || self.client.get(url.clone())).await?;
+        let exchange = self.warc.is_some().then(|| Exchange::new(&url, Vec::new(), &response));
+        let body = response.bytes().await?;
+        if let (Some(warc), Some(exchange)) = (&self.warc, &exchange) {
+            warc.record(exchange, Payload::Bytes(&body))
+                .with_context(|| format!("Cannot archive {url} in {}", warc.path().display()))?;
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, TestResponse};
+
+    const ROBOTS: &str = "
+        # comment
+        User-agent: *
+        Disallow: /private
+        Allow: /private/public
+        Disallow: /*.xls$
+
+        User-agent: other-bot
+        User-agent: rust-webscraper
+        Disallow: /search
+        Crawl-delay: 0.2
+    ";
+
+    fn url(path: &str) -> Url {
+        Url::parse(&format!("https://host{path}")).unwrap()
+    }
+
+    #[test]
+    fn star_group_applies_to_unknown_agents() {
+        let rules = RobotsRules::parse(ROBOTS, "some-bot");
+        assert!(!rules.allowed(&url("/private/x")));
+        assert!(rules.allowed(&url("/private/public/x")));
+        assert!(!rules.allowed(&url("/files/a.xls")));
+        assert!(rules.allowed(&url("/files/a.xlsx")));
+        assert!(rules.allowed(&url("/search?q=1")));
+        assert_eq!(rules.crawl_delay, None);
+    }
+
+    #[test]
+    fn own_group_replaces_star_group() {
+        let rules = RobotsRules::parse(ROBOTS, product_token("rust-webscraper/0.1 (+https://x)"));
+        assert!(rules.allowed(&url("/private/x")));
+        assert!(!rules.allowed(&url("/search?q=1")));
+        assert_eq!(rules.crawl_delay, Some(Duration::from_millis(200)));
+        assert!(RobotsRules::disallow_all().allowed(&url("/robots.txt")));
+    }
+
+    #[tokio::test]
+    async fn refuses_disallowed_urls_and_spaces_requests() {
+        let base = serve(|req| match req.path.as_str() {
+            "/robots.txt" => TestResponse::new(200, ROBOTS),
+            _ => TestResponse::new(200, ""),
+        })
+        .await;
+        let page = |p: &str| Url::parse(&format!("{base}{p}")).unwrap();
+
+        let robots = Robots::new(Client::new(), RetryPolicy::default(), "rust-webscraper/0.1", false);
+        let err = robots.admit(&page("/search")).await.unwrap_err();
+        assert!(err.to_string().contains("--ignore-robots"));
+
+        let started = std::time::Instant::now();
+        robots.admit(&page("/a")).await.unwrap();
+        robots.admit(&page("/b")).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        let ignoring = Robots::new(Client::new(), RetryPolicy::default(), "rust-webscraper/0.1", true);
+        assert!(ignoring.admit(&page("/search")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn slow_robots_txt_does_not_hold_up_other_hosts() {
+        // accepts connections but never answers
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slow = Url::parse(&format!("http://{}/x", silent.local_addr().unwrap())).unwrap();
+        let fast = serve(|_| TestResponse::new(404, "")).await;
+        let client = Client::builder().timeout(Duration::from_secs(10)).build().unwrap();
+        let robots = Arc::new(Robots::new(client, RetryPolicy::default(), "bot", false));
+
+        let stuck = tokio::spawn({
+            let robots = robots.clone();
+            async move { robots.admit(&slow).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = std::time::Instant::now();
+        robots.admit(&Url::parse(&format!("{fast}/x")).unwrap()).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(!stuck.is_finished());
+        stuck.abort();
+    }
+
+    #[tokio::test]
+    async fn unreadable_robots_txt_refuses_the_host_and_says_why() {
+        let base = serve(|_| TestResponse::new(503, "")).await;
+        let retry = RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let robots = Robots::new(Client::new(), retry, "bot", false);
+        let err = robots.admit(&Url::parse(&format!("{base}/x")).unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains("robots.txt of its host could not be read"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn missing_robots_txt_allows_everything() {
+        let base = serve(|_| TestResponse::new(404, "")).await;
+        let robots = Robots::new(Client::new(), RetryPolicy::default(), "bot", false);
+        assert!(robots.admit(&Url::parse(&format!("{base}/x")).unwrap()).await.is_ok());
+    }
+}
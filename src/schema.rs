@@ -0,0 +1,340 @@
+//! Named-field extraction: the `[schema]` table maps field names to
+//! selectors inside a container element and yields one JSON record per
+//! container, e.g. one per project card:
+//!
+//! ```toml
+//! [schema]
+//! container = "article.card"
+//!
+//! [schema.fields]
+//! title = { selector = "h3" }
+//! link = { selector = "a", attribute = "href" }
+//! date = { selector = "time", attribute = "datetime", regex = "^(\\d{4}-\\d{2}-\\d{2})" }
+//! budget = { selector = ".budget", regex = "[\\d.]+", type = "number" }
+//! tags = { selector = ".tag", list = true }
+//! documents = { selector = "li.doc", list = true, fields = { name = { selector = "a" }, url = { selector = "a", attribute = "href" } } }
+//! status = { selector = "xpath:.//dt[. = 'Status']/following-sibling::dd[1]" }
+//! ```
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use scraper::{ElementRef, Html};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+
+use crate::{
+    content::render,
+    models::{ContentMode, Record},
+    selector::ElementSelector,
+};
+
+/// What a field reads from the matched element.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldMode {
+    /// Visible text of the element and its descendants
+    #[default]
+    Text,
+    /// Inner HTML of the element
+    Html,
+    /// The element itself as HTML
+    OuterHtml,
+    /// The element converted to Markdown
+    Markdown,
+    /// Value of `attribute`
+    Attribute,
+}
+
+/// JSON type a field value is converted to.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    #[default]
+    String,
+    Number,
+    Integer,
+    Boolean,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SchemaConfig {
+    /// CSS selector (or `xpath:` expression) of the element each record is built from
+    pub container: String,
+    pub fields: BTreeMap<String, FieldConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct FieldConfig {
+    /// CSS selector (or `xpath:` expression) relative to the container;
+    /// without one the container itself
+    pub selector: Option<String>,
+    /// `text` (default), `html`, `outer_html`, `markdown`, or `attribute`
+    /// (implied by `attribute`)
+    pub mode: Option<FieldMode>,
+    pub attribute: Option<String>,
+    /// Strip surrounding whitespace, and collapse inner runs in text (default `true`)
+    pub trim: Option<bool>,
+    /// Keep only the first match of this regex (its first group, if any)
+    pub regex: Option<String>,
+    /// Collect every match into an array instead of taking the first
+    pub list: Option<bool>,
+    /// `string` (default), `number`, `integer` or `boolean`
+    #[serde(rename = "type")]
+    pub value_type: Option<ValueType>,
+    /// Build an object from each match with these fields instead of a value
+    pub fields: Option<BTreeMap<String, FieldConfig>>,
+}
+
+/// A `SchemaConfig` with its selectors and regexes compiled once.
+#[derive(Debug)]
+pub struct Schema {
+    container: ElementSelector,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    selector: Option<ElementSelector>,
+    mode: FieldMode,
+    attribute: Option<String>,
+    trim: bool,
+    regex: Option<Regex>,
+    list: bool,
+    value_type: ValueType,
+    fields: Vec<Field>,
+}
+
+impl Schema {
+    pub fn compile(cfg: &SchemaConfig) -> Result<Self> {
+        let container = ElementSelector::parse(&cfg.container).context("Invalid schema container")?;
+        if cfg.fields.is_empty() {
+            bail!("The schema has no fields");
+        }
+        Ok(Self {
+            container,
+            fields: compile_fields(&cfg.fields, "")?,
+        })
+    }
+
+    /// One record per container element in `document`.
+    pub fn records(&self, document: &Html, page_url: &str) -> Vec<Record> {
+        self.container
+            .select(document)
+            .into_iter()
+            .map(|container| Record {
+                source_url: Some(page_url.to_owned()),
+                fields: object(&self.fields, container),
+            })
+            .collect()
+    }
+}
+
+fn compile_fields(fields: &BTreeMap<String, FieldConfig>, parent: &str) -> Result<Vec<Field>> {
+    fields
+        .iter()
+        .map(|(name, cfg)| {
+            let path = format!("{parent}{name}");
+            compile_field(name, cfg, &path).with_context(|| format!("Invalid schema field `{path}`"))
+        })
+        .collect()
+}
+
+fn compile_field(name: &str, cfg: &FieldConfig, path: &str) -> Result<Field> {
+    let selector = match &cfg.selector {
+        Some(expr) => Some(ElementSelector::parse(expr)?),
+        None => None,
+    };
+    let mode = match (cfg.mode, &cfg.attribute) {
+        (None, Some(_)) => FieldMode::Attribute,
+        (Some(FieldMode::Attribute), None) => bail!("mode `attribute` needs an `attribute`"),
+        (Some(mode), Some(_)) if mode != FieldMode::Attribute => {
+            bail!("`attribute` only applies to mode `attribute`")
+        }
+        (mode, _) => mode.unwrap_or_default(),
+    };
+    let regex = match &cfg.regex {
+        Some(re) => Some(Regex::new(re).with_context(|| format!("Invalid regex `{re}`"))?),
+        None => None,
+    };
+    Ok(Field {
+        name: name.to_owned(),
+        selector,
+        mode,
+        attribute: cfg.attribute.clone(),
+        trim: cfg.trim.unwrap_or(true),
+        regex,
+        list: cfg.list.unwrap_or(false),
+        value_type: cfg.value_type.unwrap_or_default(),
+        fields: match &cfg.fields {
+            Some(nested) => compile_fields(nested, &format!("{path}."))?,
+            None => Vec::new(),
+        },
+    })
+}
+
+fn object(fields: &[Field], element: ElementRef) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|f| (f.name.clone(), f.extract(element)))
+        .collect()
+}
+
+impl Field {
+    /// A value (or array of values with `list`) from the matches inside
+    /// `element`; `null` when nothing matches or converts.
+    fn extract(&self, element: ElementRef) -> Value {
+        let matches: Vec<ElementRef> = match &self.selector {
+            Some(sel) => sel.select_in(element),
+            None => vec![element],
+        };
+        if self.list {
+            Value::Array(matches.into_iter().filter_map(|m| self.value(m)).collect())
+        } else {
+            matches.into_iter().next().and_then(|m| self.value(m)).unwrap_or(Value::Null)
+        }
+    }
+
+    fn value(&self, element: ElementRef) -> Option<Value> {
+        if !self.fields.is_empty() {
+            return Some(Value::Object(object(&self.fields, element)));
+        }
+        let raw = match self.mode {
+            FieldMode::Text => {
+                let text: String = element.text().collect();
+                if self.trim {
+                    text.split_whitespace().collect::<Vec<_>>().join(" ")
+                } else {
+                    text
+                }
+            }
+            FieldMode::Html => render(element, ContentMode::InnerHtml),
+            FieldMode::OuterHtml => render(element, ContentMode::OuterHtml),
+            FieldMode::Markdown => render(element, ContentMode::Markdown),
+            FieldMode::Attribute => element.value().attr(self.attribute.as_deref()?)?.to_owned(),
+        };
+        let raw = if self.trim { raw.trim().to_owned() } else { raw };
+        let raw = match &self.regex {
+            Some(re) => {
+                let caps = re.captures(&raw)?;
+                caps.get(1).or_else(|| caps.get(0))?.as_str().to_owned()
+            }
+            None => raw,
+        };
+        convert(raw, self.value_type)
+    }
+}
+
+fn convert(raw: String, value_type: ValueType) -> Option<Value> {
+    Some(match value_type {
+        ValueType::String => Value::String(raw),
+        ValueType::Number => Value::Number(Number::from_f64(raw.trim().parse().ok()?)?),
+        ValueType::Integer => Value::from(raw.trim().parse::<i64>().ok()?),
+        ValueType::Boolean => match raw.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "1" => Value::Bool(true),
+            "false" | "no" | "0" => Value::Bool(false),
+            _ => return None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CARDS: &str = r#"
+        <article class="card">
+          <h3>  Drone
+                swarms </h3>
+          <a href="/p/1">details</a>
+          <time datetime="2024-03-01T09:00">1 March</time>
+          <span class="budget">EUR 1500000.50</span>
+          <span class="tag">air</span><span class="tag">c2</span>
+          <ul>
+            <li class="doc"><a href="/d/1.pdf">Annex I</a></li>
+            <li class="doc"><a href="/d/2.pdf">Annex II</a></li>
+          </ul>
+        </article>
+        <article class="card"><h3>Radar</h3></article>
+    "#;
+
+    fn schema(toml_text: &str) -> Result<Schema> {
+        Schema::compile(&toml::from_str(toml_text).unwrap())
+    }
+
+    #[test]
+    fn builds_one_record_per_container() {
+        let schema = schema(
+            r#"
+            container = "article.card"
+            [fields]
+            title = { selector = "h3" }
+            link = { selector = "a", attribute = "href" }
+            date = { selector = "time", attribute = "datetime", regex = "^(\\d{4}-\\d{2}-\\d{2})" }
+            budget = { selector = ".budget", regex = "[\\d.]+", type = "number" }
+            tags = { selector = ".tag", list = true }
+            first_tag = { selector = ".tag", mode = "outer_html" }
+            documents = { selector = "li.doc", list = true, fields = { name = { selector = "a" }, url = { selector = "a", attribute = "href" } } }
+            "#,
+        )
+        .unwrap();
+        let records = schema.records(&Html::parse_document(CARDS), "https://host/list");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].source_url.as_deref(), Some("https://host/list"));
+        assert_eq!(
+            Value::Object(records[0].fields.clone()),
+            json!({
+                "title": "Drone swarms",
+                "link": "/p/1",
+                "date": "2024-03-01",
+                "budget": 1500000.5,
+                "tags": ["air", "c2"],
+                "first_tag": "<span class=\"tag\">air</span>",
+                "documents": [
+                    { "name": "Annex I", "url": "/d/1.pdf" },
+                    { "name": "Annex II", "url": "/d/2.pdf" },
+                ],
+            })
+        );
+        assert_eq!(records[1].fields["title"], json!("Radar"));
+        assert_eq!(records[1].fields["link"], Value::Null);
+        assert_eq!(records[1].fields["tags"], json!([]));
+    }
+
+    #[test]
+    fn fields_take_xpath_selectors() {
+        let compiled = schema(
+            r#"
+            container = "xpath://article[h3]"
+            [fields]
+            title = { selector = "xpath:h3" }
+            first_doc = { selector = "xpath:.//li[@class = 'doc'][1]/a", attribute = "href" }
+            documents = { selector = "li.doc", list = true, fields = { name = { selector = "xpath:a" } } }
+            "#,
+        )
+        .unwrap();
+        let records = compiled.records(&Html::parse_document(CARDS), "https://host/list");
+        assert_eq!(
+            Value::Object(records[0].fields.clone()),
+            json!({
+                "title": "Drone swarms",
+                "first_doc": "/d/1.pdf",
+                "documents": [{ "name": "Annex I" }, { "name": "Annex II" }],
+            })
+        );
+        assert_eq!(records[1].fields["first_doc"], Value::Null);
+        let err = schema("container = \"div\"\n[fields]\nx = { selector = \"xpath://[\" }").unwrap_err();
+        assert!(format!("{err:#}").contains("field `x`"));
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        let err = schema("container = \"div\"\n[fields]\nx = { mode = \"attribute\" }").unwrap_err();
+        assert!(format!("{err:#}").contains("field `x`"));
+        let err = schema("container = \"div\"\n[fields.a]\nfields = { b = { selector = \"[\" } }").unwrap_err();
+        assert!(format!("{err:#}").contains("field `a.b`"));
+        assert!(schema("container = \"div\"\n[fields]").is_err());
+    }
+}
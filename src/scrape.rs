@@ -0,0 +1,268 @@
+//! Fetching listing pages and extracting the selected elements, following
+//! pagination ("Next" links or a `{page}` URL template) when configured.
+
+use anyhow::{Context, Result};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use url::Url;
+
+use crate::{
+    config::PaginationConfig,
+    content::render,
+    context::{document_base, dom_path, nearest_heading},
+    models::{ContentMode, ExtractedElement, Record},
+    fetch::PageFetcher,
+    schema::Schema,
+    selector::ElementSelector,
+};
+
+const DEFAULT_MAX_PAGES: usize = 10;
+const DEFAULT_START_PAGE: u32 = 1;
+
+/// What is taken from every fetched page: the elements matching the
+/// selector and, with a `[schema]`, one record per container.
+#[derive(Debug)]
+pub struct Extractor {
+    selector: ElementSelector,
+    content_mode: ContentMode,
+    schema: Option<Schema>,
+}
+
+/// Elements and records gathered from one or more pages.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Extracted {
+    pub elements: Vec<ExtractedElement>,
+    #[serde(default)]
+    pub records: Vec<Record>,
+}
+
+impl Extracted {
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty() && self.records.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Extracted) {
+        self.elements.extend(other.elements);
+        self.records.extend(other.records);
+    }
+}
+
+impl std::fmt::Display for Extracted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} element(s)", self.elements.len())?;
+        if !self.records.is_empty() {
+            write!(f, ", {} record(s)", self.records.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Extractor {
+    pub fn new(selector: ElementSelector, content_mode: ContentMode, schema: Option<Schema>) -> Self {
+        Self {
+            selector,
+            content_mode,
+            schema,
+        }
+    }
+
+    pub fn extract(&self, html: &str, page_url: &str) -> Extracted {
+        let document = Html::parse_document(html);
+        Extracted {
+            elements: extract_elements(&document, &self.selector, self.content_mode, page_url),
+            records: self
+                .schema
+                .as_ref()
+                .map(|s| s.records(&document, page_url))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Every element of `document` matching `selector`, its content rendered as
+/// `mode` and tagged with `page_url` and its place on the page.
+pub fn extract_elements(
+    document: &Html,
+    selector: &ElementSelector,
+    mode: ContentMode,
+    page_url: &str,
+) -> Vec<ExtractedElement> {
+    let base = document_base(document, page_url);
+    selector
+        .select(document)
+        .into_iter()
+        .enumerate()
+        .map(|(index, element)| {
+            let attributes = element
+                .value()
+                .attrs()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>();
+            ExtractedElement {
+                tag: element.value().name().to_string(),
+                content: render(element, mode),
+                attributes: if attributes.is_empty() { None } else { Some(attributes) },
+                content_mode: mode,
+                source_url: Some(page_url.to_owned()),
+                dom_path: Some(dom_path(element)),
+                index: Some(index),
+                heading: nearest_heading(element),
+                resolved_url: element
+                    .value()
+                    .attr("href")
+                    .and_then(|href| base.as_ref()?.join(href.trim()).ok())
+                    .map(String::from),
+            }
+        })
+        .collect()
+}
+
+/// Absolute URL of the first `next_selector` match carrying an `href`.
+fn next_page(html: &str, next_selector: &ElementSelector, page_url: &Url) -> Option<Url> {
+    let document = Html::parse_document(html);
+    let href = next_selector
+        .select(&document)
+        .into_iter()
+        .find_map(|el| el.value().attr("href"))?;
+    page_url.join(href).ok()
+}
+
+/// Fetch `url` and, if `[pagination]` is configured, the pages after it, and
+/// return the union of what `extractor` finds on them.
+/// Pages come from `next_selector` (the `href` of a "Next" link) or from
+/// `url_template`, whose `{page}` placeholder counts up from `start_page`
+/// (the template then replaces `url`). Crawling stops after `max_pages`,
+/// at a page seen before, when there is no next link, or — for templates —
+/// at the first page that fails or has no matching elements.
+pub async fn scrape_pages(
+    fetcher: &PageFetcher,
+    url: &str,
+    extractor: &Extractor,
+    pagination: &PaginationConfig,
+) -> Result<Extracted> {
+    let next_selector = match &pagination.next_selector {
+        Some(expr) => Some(
+            ElementSelector::parse(expr).context("Invalid pagination next_selector")?,
+        ),
+        None => None,
+    };
+    let template = pagination.url_template.as_deref();
+    let max_pages = match (&next_selector, template) {
+        (None, None) => 1,
+        _ => pagination.max_pages.unwrap_or(DEFAULT_MAX_PAGES).max(1),
+    };
+    let start_page = pagination.start_page.unwrap_or(DEFAULT_START_PAGE);
+
+    let page_url = |n: usize| -> Result<Url> {
+        let raw = match template {
+            Some(t) => t.replace("{page}", &(start_page as usize + n).to_string()),
+            None => url.to_owned(),
+        };
+        Url::parse(&raw).with_context(|| format!("Invalid page URL {raw}"))
+    };
+
+    let mut extracted = Extracted::default();
+    let mut seen = HashSet::new();
+    let mut next = Some(page_url(0)?);
+    let mut fetched = 0;
+    while let Some(current) = next.take() {
+        if fetched == max_pages {
+            println!("Stopping after {max_pages} page(s)");
+            break;
+        }
+        if !seen.insert(current.clone()) {
+            break; // pagination loops back
+        }
+
+        let html = match fetcher.fetch(&current).await {
+            Ok(page) => page.text(),
+            // a template runs past the last page sooner or later
+            Err(e) if fetched > 0 => {
+                println!("Stopping pagination at {current}: {e:#}");
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        fetched += 1;
+
+        let found = extractor.extract(&html, current.as_str());
+        println!("Fetched {current}: {found}");
+        if found.is_empty() && template.is_some() && fetched > 1 {
+            break; // past the last page
+        }
+        extracted.extend(found);
+
+        next = match (&next_selector, template) {
+            (Some(sel), _) => next_page(&html, sel, &current),
+            (None, Some(_)) => Some(page_url(fetched)?),
+            (None, None) => None,
+        };
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, TestResponse};
+
+    fn page(links: &[&str], next: Option<&str>) -> String {
+        let mut html: String = links.iter().map(|l| format!("<a href=\"{l}\">{l}</a>")).collect();
+        if let Some(next) = next {
+            html.push_str(&format!("<nav><a class=\"next\" href=\"{next}\">Next</a></nav>"));
+        }
+        html
+    }
+
+    #[tokio::test]
+    async fn follows_next_links_up_to_the_limit() {
+        let base = serve(|req| match req.path.as_str() {
+            "/list" => TestResponse::new(200, page(&["/a.pdf"], Some("/list?p=2"))),
+            "/list?p=2" => TestResponse::new(200, page(&["/b.pdf"], Some("?p=3"))),
+            "/list?p=3" => TestResponse::new(200, page(&["/c.pdf"], Some("/list"))),
+            _ => TestResponse::new(404, ""),
+        })
+        .await;
+        let extractor = Extractor::new(ElementSelector::parse("a:not(.next)").unwrap(), ContentMode::default(), None);
+        let mut pagination = PaginationConfig {
+            next_selector: Some("a.next".into()),
+            ..Default::default()
+        };
+        let url = format!("{base}/list");
+        let fetcher = PageFetcher::for_tests();
+
+        let all = scrape_pages(&fetcher, &url, &extractor, &pagination).await.unwrap().elements;
+        let hrefs: Vec<_> = all.iter().map(|e| e.attributes.as_ref().unwrap()["href"].as_str()).collect();
+        assert_eq!(hrefs, ["/a.pdf", "/b.pdf", "/c.pdf"]);
+        assert_eq!(all[1].source_url.as_deref(), Some(format!("{base}/list?p=2").as_str()));
+        assert_eq!(all[1].resolved_url.as_deref(), Some(format!("{base}/b.pdf").as_str()));
+        assert_eq!((all[1].index, all[1].dom_path.as_deref()), (Some(0), Some("html > body > a")));
+
+        pagination.max_pages = Some(2);
+        let limited = scrape_pages(&fetcher, &url, &extractor, &pagination).await.unwrap().elements;
+        assert_eq!(limited.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn url_template_stops_at_empty_page() {
+        let base = serve(|req| match req.path.as_str() {
+            "/list?page=0" => TestResponse::new(200, page(&["/a.pdf", "/b.pdf"], None)),
+            "/list?page=1" => TestResponse::new(200, page(&["/c.pdf"], None)),
+            _ => TestResponse::new(200, page(&[], None)),
+        })
+        .await;
+        let pagination = PaginationConfig {
+            url_template: Some(format!("{base}/list?page={{page}}")),
+            start_page: Some(0),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(ElementSelector::parse("a").unwrap(), ContentMode::default(), None);
+        let all = scrape_pages(&PageFetcher::for_tests(), "unused", &extractor, &pagination)
+            .await
+            .unwrap()
+            .elements;
+        assert_eq!(all.len(), 3);
+        assert!(all[2].source_url.as_deref().unwrap().ends_with("page=1"));
+    }
+}
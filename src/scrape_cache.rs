@@ -0,0 +1,205 @@
+//! Cache of scrape results, one file per combination of inputs.
+//!
+//! The key hashes everything that shapes the extracted data (URL, selector,
+//! content mode, schema and the pagination / crawl / sitemap settings), so a
+//! run never reuses data produced for other inputs. Entries expire after the
+//! configured TTL and can be dropped with `--force-scrape` or `--clear-cache`.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{CacheConfig, CrawlConfig, PaginationConfig, SitemapConfig},
+    manifest::sha256_hex,
+    models::ContentMode,
+    schema::SchemaConfig,
+    scrape::Extracted,
+};
+
+const DEFAULT_DIR: &str = "backup/scrape_cache";
+const DEFAULT_TTL_HOURS: u64 = 24;
+
+/// The inputs a scrape result depends on.
+#[derive(Debug, Serialize)]
+pub struct CacheKey<'a> {
+    pub url: &'a str,
+    pub selector: &'a str,
+    pub content_mode: ContentMode,
+    pub schema: Option<&'a SchemaConfig>,
+    pub pagination: &'a PaginationConfig,
+    pub crawl: &'a CrawlConfig,
+    pub sitemap: &'a SitemapConfig,
+}
+
+impl CacheKey<'_> {
+    /// Hash of the key's canonical JSON form.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cache key serializes");
+        sha256_hex(&json)[..16].to_owned()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Kept for people browsing the cache directory
+    url: String,
+    selector: String,
+    scraped_at: DateTime<Utc>,
+    #[serde(flatten)]
+    extracted: Extracted,
+}
+
+pub struct ScrapeCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ScrapeCache {
+    pub fn new(cfg: &CacheConfig) -> Self {
+        let hours = cfg.ttl_hours.unwrap_or(DEFAULT_TTL_HOURS);
+        Self {
+            dir: PathBuf::from(cfg.dir.as_deref().unwrap_or(DEFAULT_DIR)),
+            ttl: i64::try_from(hours)
+                .ok()
+                .and_then(Duration::try_hours)
+                .unwrap_or(Duration::MAX),
+        }
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{}.json", key.hash()))
+    }
+
+    /// The cached result for `key` and when it was scraped, unless there is
+    /// none or it has expired. An unreadable entry counts as missing.
+    pub fn load(&self, key: &CacheKey) -> Result<Option<(Extracted, DateTime<Utc>)>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let entry: CacheEntry = match read_entry(&path) {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Ignoring unreadable cache entry {}: {e:#}", path.display());
+                return Ok(None);
+            }
+        };
+        if Utc::now() - entry.scraped_at > self.ttl {
+            println!("Cached scrape {} has expired", path.display());
+            return Ok(None);
+        }
+        Ok(Some((entry.extracted, entry.scraped_at)))
+    }
+
+    /// Store `extracted` under `key`, replacing any earlier entry, and drop
+    /// expired entries of other keys.
+    pub fn store(&self, key: &CacheKey, extracted: Extracted) -> Result<Extracted> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Cannot create {}", self.dir.display()))?;
+        self.prune()?;
+        let entry = CacheEntry {
+            url: key.url.to_owned(),
+            selector: key.selector.to_owned(),
+            scraped_at: Utc::now(),
+            extracted,
+        };
+        let path = self.path(key);
+        let json = serde_json::to_string(&entry)?;
+        fs::write(&path, json).with_context(|| format!("Cannot write {}", path.display()))?;
+        Ok(entry.extracted)
+    }
+
+    /// Remove every entry; returns how many there were.
+    pub fn clear(&self) -> Result<usize> {
+        self.remove_where(|_| true)
+    }
+
+    fn prune(&self) -> Result<usize> {
+        let now = Utc::now();
+        self.remove_where(|path| read_entry(path).is_ok_and(|e| now - e.scraped_at > self.ttl))
+    }
+
+    fn remove_where(&self, remove: impl Fn(&Path) -> bool) -> Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") && remove(&path) {
+                fs::remove_file(&path).with_context(|| format!("Cannot remove {}", path.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn read_entry(path: &Path) -> Result<CacheEntry> {
+    let file = fs::File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("Cannot decode {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ExtractedElement;
+
+    fn extracted(content: &str) -> Extracted {
+        Extracted {
+            elements: vec![ExtractedElement {
+                tag: "a".into(),
+                content: content.into(),
+                ..Default::default()
+            }],
+            records: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn entries_are_keyed_by_inputs_and_expire() {
+        let dir = "tmp_scrape_cache";
+        let cfg = CacheConfig {
+            dir: Some(dir.into()),
+            ttl_hours: Some(1),
+        };
+        let cache = ScrapeCache::new(&cfg);
+        let (pagination, crawl, sitemap) = Default::default();
+        let key = |url| CacheKey {
+            url,
+            selector: "a",
+            content_mode: ContentMode::Text,
+            schema: None,
+            pagination: &pagination,
+            crawl: &crawl,
+            sitemap: &sitemap,
+        };
+
+        assert!(cache.load(&key("https://a")).unwrap().is_none());
+        cache.store(&key("https://a"), extracted("A")).unwrap();
+        let (hit, _) = cache.load(&key("https://a")).unwrap().unwrap();
+        assert_eq!(hit.elements[0].content, "A");
+        assert!(cache.load(&key("https://b")).unwrap().is_none(), "other URL, other entry");
+        let other_mode = CacheKey { content_mode: ContentMode::Markdown, ..key("https://a") };
+        assert!(cache.load(&other_mode).unwrap().is_none());
+
+        // backdate the entry past the TTL
+        let path = cache.path(&key("https://a"));
+        let mut entry = read_entry(&path).unwrap();
+        entry.scraped_at -= Duration::hours(2);
+        fs::write(&path, serde_json::to_string(&entry).unwrap()).unwrap();
+        assert!(cache.load(&key("https://a")).unwrap().is_none());
+
+        cache.store(&key("https://b"), extracted("B")).unwrap();
+        assert!(!path.exists(), "expired entries are pruned");
+        assert_eq!(cache.clear().unwrap(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
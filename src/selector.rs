@@ -0,0 +1,207 @@
+//! Element selectors: CSS by default, XPath 1.0 when prefixed with `xpath:`.
+//!
+//! XPath runs against an XML copy of the parsed HTML in which element and
+//! attribute names carry no namespace, so `//table` works as in a browser.
+//! Selected attributes and text nodes stand for their parent element.
+//! Selecting inside an element evaluates the XPath with that element as the
+//! context node, so `.//h3` and `h3` are relative to it.
+
+use anyhow::{anyhow, bail, Result};
+use ego_tree::{NodeId, Tree};
+use scraper::{ElementRef, Html, Node, Selector};
+use std::{collections::HashMap, fmt};
+use sxd_document::{dom, Package};
+use sxd_xpath::{nodeset, Context, Factory, Value, XPath};
+
+pub const XPATH_PREFIX: &str = "xpath:";
+
+pub enum ElementSelector {
+    Css(Selector),
+    XPath { expr: String, xpath: XPath },
+}
+
+impl fmt::Debug for ElementSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElementSelector::Css(sel) => f.debug_tuple("Css").field(sel).finish(),
+            ElementSelector::XPath { expr, .. } => f.debug_tuple("XPath").field(expr).finish(),
+        }
+    }
+}
+
+impl ElementSelector {
+    /// A CSS selector, or an XPath expression after `xpath:`.
+    pub fn parse(expr: &str) -> Result<Self> {
+        match expr.strip_prefix(XPATH_PREFIX) {
+            Some(xpath) => {
+                let compiled = Factory::new()
+                    .build(xpath.trim())
+                    .map_err(|e| anyhow!("Invalid XPath `{xpath}`: {e}"))?
+                    .ok_or_else(|| anyhow!("Empty XPath `{expr}`"))?;
+                Ok(ElementSelector::XPath {
+                    expr: xpath.trim().to_owned(),
+                    xpath: compiled,
+                })
+            }
+            None => Selector::parse(expr)
+                .map(ElementSelector::Css)
+                .map_err(|e| anyhow!("Invalid CSS selector `{expr}`: {e:?}")),
+        }
+    }
+
+    /// Matching elements in document order. An XPath that fails to evaluate
+    /// or does not yield nodes selects nothing (and says why).
+    pub fn select<'a>(&self, document: &'a Html) -> Vec<ElementRef<'a>> {
+        match self {
+            ElementSelector::Css(sel) => document.select(sel).collect(),
+            ElementSelector::XPath { expr, xpath } => {
+                select_xpath(&document.tree, None, xpath).unwrap_or_else(|e| {
+                    println!("Warning: XPath `{expr}`: {e:#}");
+                    Vec::new()
+                })
+            }
+        }
+    }
+
+    /// Like [`select`](Self::select), but CSS matches descendants of
+    /// `element` and XPath is evaluated with `element` as its context node.
+    pub fn select_in<'a>(&self, element: ElementRef<'a>) -> Vec<ElementRef<'a>> {
+        match self {
+            ElementSelector::Css(sel) => element.select(sel).collect(),
+            ElementSelector::XPath { expr, xpath } => {
+                select_xpath(element.tree(), Some(element.id()), xpath).unwrap_or_else(|e| {
+                    println!("Warning: XPath `{expr}`: {e:#}");
+                    Vec::new()
+                })
+            }
+        }
+    }
+}
+
+// `dom::Element` hashes by node address, its interior mutability never
+// changes the key
+#[allow(clippy::mutable_key_type)]
+fn select_xpath<'a>(tree: &'a Tree<Node>, context: Option<NodeId>, xpath: &XPath) -> Result<Vec<ElementRef<'a>>> {
+    let package = Package::new();
+    let xml = package.as_document();
+    let mut ids = HashMap::new();
+    for child in tree.root().children() {
+        if let Some(el) = ElementRef::wrap(child) {
+            let copy = copy_element(xml, el, &mut ids);
+            xml.root().append_child(copy);
+        }
+    }
+
+    let node: nodeset::Node = match context {
+        Some(context) => ids
+            .iter()
+            .find(|(_, id)| **id == context)
+            .map(|(copy, _)| (*copy).into())
+            .ok_or_else(|| anyhow!("the context element is not in the document"))?,
+        None => xml.root().into(),
+    };
+    let value = xpath.evaluate(&Context::new(), node).map_err(|e| anyhow!("{e}"))?;
+    let Value::Nodeset(nodes) = value else {
+        bail!("the expression does not select nodes");
+    };
+
+    let mut selected = Vec::new();
+    for node in nodes.document_order() {
+        let element = match node {
+            nodeset::Node::Element(e) => Some(e),
+            nodeset::Node::Attribute(a) => a.parent(),
+            nodeset::Node::Text(t) => t.parent(),
+            _ => None,
+        };
+        let found = element
+            .and_then(|e| ids.get(&e))
+            .and_then(|id| tree.get(*id))
+            .and_then(ElementRef::wrap);
+        if let Some(found) = found {
+            if !selected.contains(&found) {
+                selected.push(found);
+            }
+        }
+    }
+    Ok(selected)
+}
+
+/// Copy `el` and its subtree into `xml`, remembering which HTML node every
+/// XML element came from.
+#[allow(clippy::mutable_key_type)]
+fn copy_element<'d>(
+    xml: dom::Document<'d>,
+    el: ElementRef,
+    ids: &mut HashMap<dom::Element<'d>, ego_tree::NodeId>,
+) -> dom::Element<'d> {
+    let copy = xml.create_element(el.value().name());
+    for (name, value) in el.value().attrs() {
+        copy.set_attribute_value(name, value);
+    }
+    ids.insert(copy, el.id());
+    for child in el.children() {
+        match child.value() {
+            Node::Text(text) => copy.append_child(xml.create_text(text)),
+            Node::Comment(comment) => copy.append_child(xml.create_comment(comment)),
+            Node::Element(_) => {
+                let child = ElementRef::wrap(child).expect("element node");
+                copy.append_child(copy_element(xml, child, ids));
+            }
+            _ => {}
+        }
+    }
+    copy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = r#"<html><body>
+        <h2>Other projects</h2><table id="other"><tr><td>x</td></tr></table>
+        <h2>Selected projects</h2>
+        <p>intro</p>
+        <table id="selected"><tr><td><a href="/a.pdf">A</a></td></tr></table>
+    </body></html>"#;
+
+    fn ids(selector: &str) -> Vec<String> {
+        let document = Html::parse_document(HTML);
+        ElementSelector::parse(selector)
+            .unwrap()
+            .select(&document)
+            .iter()
+            .map(|el| el.value().id().unwrap_or(el.value().name()).to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn css_and_xpath_select_the_same_records() {
+        assert_eq!(ids("table"), ["other", "selected"]);
+        assert_eq!(
+            ids("xpath://h2[contains(., 'Selected projects')]/following-sibling::table[1]"),
+            ["selected"]
+        );
+        assert_eq!(ids("xpath://a/@href"), ["a"]);
+        assert!(ids("xpath:count(//table)").is_empty());
+        assert!(ElementSelector::parse("xpath://[").is_err());
+        assert!(ElementSelector::parse("div[").is_err());
+    }
+
+    #[test]
+    fn xpath_inside_an_element_is_relative_to_it() {
+        let document = Html::parse_document(HTML);
+        let table = ElementSelector::parse("#selected").unwrap().select(&document)[0];
+        let names = |selector: &str| -> Vec<String> {
+            ElementSelector::parse(selector)
+                .unwrap()
+                .select_in(table)
+                .iter()
+                .map(|el| el.inner_html())
+                .collect()
+        };
+        assert_eq!(names("xpath:.//a"), ["A"]);
+        assert_eq!(names("xpath:.//td"), names("td"));
+        assert_eq!(names("xpath:preceding-sibling::p"), ["intro"]);
+        assert!(names("xpath:.//h2").is_empty());
+    }
+}
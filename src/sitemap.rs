@@ -0,0 +1,377 @@
+//! Seeding the scrape from a site's sitemap: `sitemap.xml` files, sitemap
+//! indexes and gzip-compressed sitemaps are read, their entries filtered by
+//! URL regex and `lastmod` date, and the selector extraction runs on every
+//! matched page.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate};
+use flate2::read::GzDecoder;
+use quick_xml::{escape::resolve_predefined_entity, events::Event, Reader as XmlReader};
+use regex::Regex;
+use std::{
+    collections::{HashSet, VecDeque},
+    io::Read,
+};
+use url::Url;
+
+use crate::{
+    config::SitemapConfig,
+    fetch::PageFetcher,
+    scrape::{Extracted, Extractor},
+};
+
+const DEFAULT_MAX_PAGES: usize = 100;
+const DEFAULT_MAX_SITEMAPS: usize = 50;
+/// Largest sitemap the protocol allows, uncompressed
+const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+
+/// One `<url>` or `<sitemap>` entry.
+#[derive(Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: String,
+    pub lastmod: Option<NaiveDate>,
+}
+
+/// A parsed sitemap file: either pages or, for a sitemap index, more sitemaps.
+#[derive(Debug, PartialEq)]
+pub enum Sitemap {
+    Urls(Vec<SitemapEntry>),
+    Index(Vec<SitemapEntry>),
+}
+
+/// Parse a sitemap (`<urlset>`) or sitemap index (`<sitemapindex>`),
+/// gunzipping it first if it starts with the gzip magic bytes.
+pub fn parse_sitemap(bytes: &[u8]) -> Result<Sitemap> {
+    let xml = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut out = Vec::new();
+        GzDecoder::new(bytes)
+            .take(MAX_SITEMAP_BYTES + 1)
+            .read_to_end(&mut out)
+            .context("Cannot decompress gzip sitemap")?;
+        if out.len() as u64 > MAX_SITEMAP_BYTES {
+            bail!("Sitemap is larger than {} MiB uncompressed", MAX_SITEMAP_BYTES / 1024 / 1024);
+        }
+        out
+    } else if bytes.len() as u64 > MAX_SITEMAP_BYTES {
+        bail!("Sitemap is larger than {} MiB", MAX_SITEMAP_BYTES / 1024 / 1024);
+    } else {
+        bytes.to_vec()
+    };
+
+    let mut reader = XmlReader::from_reader(xml.as_slice());
+    let mut buf = Vec::new();
+    let mut is_index = None;
+    let mut entries = Vec::new();
+    let mut current: Option<(String, Option<String>)> = None;
+    // element whose text is being collected: `loc` or `lastmod`
+    let mut field: Option<Vec<u8>> = None;
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"urlset" if is_index.is_none() => is_index = Some(false),
+                b"sitemapindex" if is_index.is_none() => is_index = Some(true),
+                b"url" | b"sitemap" => current = Some((String::new(), None)),
+                name @ (b"loc" | b"lastmod") if current.is_some() => {
+                    field = Some(name.to_vec());
+                    text.clear();
+                }
+                _ => {}
+            },
+            Event::Text(t) if field.is_some() => text.push_str(&t.decode()?),
+            Event::CData(t) if field.is_some() => text.push_str(&String::from_utf8_lossy(&t)),
+            Event::GeneralRef(r) if field.is_some() => {
+                if let Some(c) = r.resolve_char_ref()? {
+                    text.push(c);
+                } else if let Some(s) = resolve_predefined_entity(&r.decode()?) {
+                    text.push_str(s);
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"loc" | b"lastmod" if field.is_some() => {
+                    if let (Some(name), Some((loc, lastmod))) = (field.take(), current.as_mut()) {
+                        let value = text.trim().to_owned();
+                        if name == b"loc" {
+                            *loc = value;
+                        } else {
+                            *lastmod = Some(value);
+                        }
+                    }
+                }
+                b"url" | b"sitemap" => {
+                    if let Some((loc, lastmod)) = current.take() {
+                        if !loc.is_empty() {
+                            entries.push(SitemapEntry {
+                                loc,
+                                lastmod: lastmod.as_deref().and_then(parse_lastmod),
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    match is_index {
+        Some(true) => Ok(Sitemap::Index(entries)),
+        Some(false) => Ok(Sitemap::Urls(entries)),
+        None => bail!("Not a sitemap: no <urlset> or <sitemapindex> element"),
+    }
+}
+
+/// W3C datetime as used by `lastmod`: `2024-05-01`, `2024-05-01T10:00:00+02:00`,
+/// or the coarser `2024-05` / `2024` (first day of the period).
+pub fn parse_lastmod(value: &str) -> Option<NaiveDate> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.date_naive());
+    }
+    // date part of datetimes without seconds, e.g. `2024-05-01T10:00Z`
+    let date = value.split('T').next().unwrap_or(value);
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(&format!("{date}-01"), "%Y-%m-%d"))
+        .or_else(|_| NaiveDate::parse_from_str(&format!("{date}-01-01"), "%Y-%m-%d"))
+        .ok()
+}
+
+/// Which sitemap entries are scraped.
+#[derive(Debug)]
+pub struct SitemapFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    since: Option<NaiveDate>,
+}
+
+impl SitemapFilter {
+    pub fn new(cfg: &SitemapConfig) -> Result<Self> {
+        let compile = |patterns: &Option<Vec<String>>| -> Result<Vec<Regex>> {
+            patterns
+                .iter()
+                .flatten()
+                .map(|p| Regex::new(p).with_context(|| format!("Invalid sitemap pattern `{p}`")))
+                .collect()
+        };
+        let since = match &cfg.modified_since {
+            Some(s) => Some(
+                parse_lastmod(s).with_context(|| format!("Invalid sitemap modified_since `{s}`"))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            include: compile(&cfg.include)?,
+            exclude: compile(&cfg.exclude)?,
+            since,
+        })
+    }
+
+    /// Not modified before `modified_since` (entries without `lastmod` pass).
+    fn recent(&self, entry: &SitemapEntry) -> bool {
+        match (self.since, entry.lastmod) {
+            (Some(since), Some(lastmod)) => lastmod >= since,
+            _ => true,
+        }
+    }
+
+    /// Recent, matching at least one `include` pattern (when there are any)
+    /// and no `exclude` pattern.
+    pub fn allows(&self, entry: &SitemapEntry) -> bool {
+        self.recent(entry)
+            && (self.include.is_empty() || self.include.iter().any(|re| re.is_match(&entry.loc)))
+            && !self.exclude.iter().any(|re| re.is_match(&entry.loc))
+    }
+}
+
+/// Read the sitemap at `sitemap_url` (following sitemap indexes) and return
+/// the page URLs passing the filters, in sitemap order and without duplicates.
+/// Nested sitemaps whose `lastmod` predates `modified_since` are skipped,
+/// unreadable nested sitemaps are logged and skipped.
+pub async fn sitemap_pages(
+    fetcher: &PageFetcher,
+    sitemap_url: &Url,
+    cfg: &SitemapConfig,
+) -> Result<Vec<Url>> {
+    let filter = SitemapFilter::new(cfg)?;
+    let max_pages = cfg.max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+    let max_sitemaps = cfg.max_sitemaps.unwrap_or(DEFAULT_MAX_SITEMAPS);
+
+    let mut queue = VecDeque::from([sitemap_url.clone()]);
+    let mut seen_sitemaps = HashSet::from([sitemap_url.clone()]);
+    let mut seen_pages = HashSet::new();
+    let mut pages = Vec::new();
+    let mut read = 0;
+
+    while let Some(url) = queue.pop_front() {
+        if read == max_sitemaps {
+            println!("Stopping after {max_sitemaps} sitemap(s)");
+            break;
+        }
+        read += 1;
+        let sitemap = match fetcher
+            .fetch_at_most(&url, MAX_SITEMAP_BYTES)
+            .await
+            .and_then(|page| parse_sitemap(&page.body))
+        {
+            Ok(sitemap) => sitemap,
+            // the top-level sitemap is the whole seed, so that one must work
+            Err(e) if &url == sitemap_url => return Err(e),
+            Err(e) => {
+                println!("Cannot read sitemap {url}: {e:#}");
+                continue;
+            }
+        };
+        match sitemap {
+            Sitemap::Index(entries) => {
+                for entry in entries.iter().filter(|e| filter.recent(e)) {
+                    match url.join(&entry.loc) {
+                        Ok(nested) if seen_sitemaps.insert(nested.clone()) => queue.push_back(nested),
+                        Ok(_) => {}
+                        Err(e) => println!("Skip sitemap entry {}: {e}", entry.loc),
+                    }
+                }
+            }
+            Sitemap::Urls(entries) => {
+                let before = pages.len();
+                for entry in entries.iter().filter(|e| filter.allows(e)) {
+                    match url.join(&entry.loc) {
+                        Ok(page) if seen_pages.insert(page.clone()) => pages.push(page),
+                        Ok(_) => {}
+                        Err(e) => println!("Skip sitemap entry {}: {e}", entry.loc),
+                    }
+                }
+                println!(
+                    "Sitemap {url}: {} of {} page(s) selected",
+                    pages.len() - before,
+                    entries.len()
+                );
+            }
+        }
+    }
+
+    if pages.len() > max_pages {
+        println!("Keeping the first {max_pages} of {} sitemap page(s)", pages.len());
+        pages.truncate(max_pages);
+    }
+    Ok(pages)
+}
+
+/// Scrape every page selected from the sitemap at `cfg.url` (resolved
+/// against `base`, the configured `url`) and return what `extractor` finds
+/// on them. Pages that fail are logged and skipped, pages that are not HTML
+/// are skipped without being read.
+pub async fn scrape_sitemap(
+    fetcher: &PageFetcher,
+    base: &str,
+    extractor: &Extractor,
+    cfg: &SitemapConfig,
+) -> Result<Extracted> {
+    let raw = cfg.url.as_deref().context("No sitemap URL configured")?;
+    let sitemap_url = match Url::parse(base) {
+        Ok(base) => base.join(raw),
+        Err(_) => Url::parse(raw),
+    }
+    .with_context(|| format!("Invalid sitemap URL {raw}"))?;
+
+    let pages = sitemap_pages(fetcher, &sitemap_url, cfg).await?;
+    let mut extracted = Extracted::default();
+    for page in &pages {
+        match fetcher.fetch_html(page).await {
+            Ok(Some(fetched)) => {
+                let found = extractor.extract(&fetched.text(), page.as_str());
+                println!("Fetched {page}: {found}");
+                extracted.extend(found);
+            }
+            Ok(None) => println!("Skip {page}, not an HTML page"),
+            Err(e) => println!("Cannot scrape {page}: {e:#}"),
+        }
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::ContentMode,
+        selector::ElementSelector,
+        test_server::{serve, TestResponse},
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn parses_urlsets_indexes_and_lastmod_formats() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>https://host/a?x=1&amp;y=2</loc><lastmod>2024-05-01T10:00:00+02:00</lastmod></url>
+              <url><loc> https://host/b </loc><lastmod>2023-07</lastmod></url>
+              <url><loc><![CDATA[https://host/c]]></loc></url>
+            </urlset>"#;
+        let Sitemap::Urls(entries) = parse_sitemap(&gzip(xml)).unwrap() else {
+            panic!("expected a urlset");
+        };
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].loc, "https://host/a?x=1&y=2");
+        assert_eq!(entries[0].lastmod, NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(entries[1].loc, "https://host/b");
+        assert_eq!(entries[1].lastmod, NaiveDate::from_ymd_opt(2023, 7, 1));
+        assert_eq!(entries[2].lastmod, None);
+
+        let index = "<sitemapindex><sitemap><loc>/s1.xml.gz</loc></sitemap></sitemapindex>";
+        assert!(matches!(parse_sitemap(index.as_bytes()).unwrap(), Sitemap::Index(e) if e.len() == 1));
+        assert!(parse_sitemap(b"<html></html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn follows_indexes_and_filters_entries() {
+        let base = serve(|req| match req.path.as_str() {
+            "/sitemap.xml" => TestResponse::new(
+                200,
+                "<sitemapindex>
+                   <sitemap><loc>/projects.xml.gz</loc><lastmod>2024-02-01</lastmod></sitemap>
+                   <sitemap><loc>/archive.xml</loc><lastmod>2019-01-01</lastmod></sitemap>
+                 </sitemapindex>",
+            ),
+            "/projects.xml.gz" => TestResponse::new(
+                200,
+                gzip(
+                    "<urlset>
+                       <url><loc>/projects/new</loc><lastmod>2024-01-15</lastmod></url>
+                       <url><loc>/projects/old</loc><lastmod>2020-01-01</lastmod></url>
+                       <url><loc>/news/x</loc></url>
+                       <url><loc>/projects/undated</loc></url>
+                       <url><loc>/projects/report.pdf</loc></url>
+                     </urlset>",
+                ),
+            ),
+            "/projects/new" => TestResponse::new(200, "<a href=\"/new.pdf\">new</a>"),
+            "/projects/undated" => TestResponse::new(200, "<a href=\"/undated.pdf\">undated</a>"),
+            "/projects/report.pdf" => TestResponse::new(200, "%PDF-1.4 <a href=\"/x\">x</a>")
+                .header("Content-Type", "application/pdf"),
+            _ => TestResponse::new(404, ""),
+        })
+        .await;
+        let cfg = SitemapConfig {
+            url: Some("/sitemap.xml".into()),
+            include: Some(vec!["/projects/".into()]),
+            modified_since: Some("2024-01-01".into()),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(ElementSelector::parse("a").unwrap(), ContentMode::default(), None);
+        let elements = scrape_sitemap(&PageFetcher::for_tests(), &base, &extractor, &cfg)
+            .await
+            .unwrap()
+            .elements;
+        let pages: Vec<_> = elements.iter().map(|e| e.source_url.as_deref().unwrap()).collect();
+        assert_eq!(pages, [format!("{base}/projects/new"), format!("{base}/projects/undated")]);
+    }
+}
@@ -0,0 +1,152 @@
+//! Raw page snapshots: every fetched page is kept, gzip-compressed, with its
+//! status, headers and fetch time, so the extraction can be re-run offline.
+//!
+//! A snapshot file holds one line of JSON metadata followed by the body
+//! exactly as the server sent it. There is one file per URL; a later fetch
+//! replaces it.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use encoding_rs::Encoding;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+};
+use url::Url;
+
+use crate::{charset, config::SnapshotConfig, manifest::sha256_hex};
+
+const DEFAULT_DIR: &str = "backup/snapshots";
+
+/// A fetched page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page {
+    /// URL that was requested
+    pub url: String,
+    /// URL after redirects
+    pub final_url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub fetched_at: DateTime<Utc>,
+    /// Encoding detected when the page was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl Page {
+    /// First value of header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Served as HTML, or without a `Content-Type`.
+    pub fn is_html(&self) -> bool {
+        self.header("content-type")
+            .is_none_or(|ct| ct.to_ascii_lowercase().contains("html"))
+    }
+
+    /// Encoding of the body: the recorded one, or detected from the
+    /// headers and the body for pages stored without it.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.charset
+            .as_deref()
+            .and_then(|label| Encoding::for_label(label.as_bytes()))
+            .unwrap_or_else(|| charset::detect(self.header("content-type"), &self.body))
+    }
+
+    /// The body decoded with `encoding()`.
+    pub fn text(&self) -> String {
+        charset::decode(&self.body, self.encoding())
+    }
+}
+
+#[derive(Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(cfg: &SnapshotConfig) -> Self {
+        Self {
+            dir: PathBuf::from(cfg.dir.as_deref().unwrap_or(DEFAULT_DIR)),
+        }
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.snap.gz", &sha256_hex(url.as_bytes())[..16]))
+    }
+
+    pub fn save(&self, page: &Page) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Cannot create {}", self.dir.display()))?;
+        let path = self.path(&page.url);
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut gz, page)?;
+        gz.write_all(b"\n")?;
+        gz.write_all(&page.body)?;
+        fs::write(&path, gz.finish()?).with_context(|| format!("Cannot write {}", path.display()))
+    }
+
+    /// The snapshot of `url`, if one was stored.
+    pub fn load(&self, url: &Url) -> Result<Option<Page>> {
+        let path = self.path(url.as_str());
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut raw = Vec::new();
+        GzDecoder::new(fs::File::open(&path)?)
+            .read_to_end(&mut raw)
+            .with_context(|| format!("Cannot decompress {}", path.display()))?;
+        let split = raw
+            .iter()
+            .position(|b| *b == b'\n')
+            .with_context(|| format!("{} has no metadata line", path.display()))?;
+        let mut page: Page = serde_json::from_slice(&raw[..split])
+            .with_context(|| format!("Cannot decode {}", path.display()))?;
+        page.body = raw.split_off(split + 1);
+        Ok(Some(page))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_pages() {
+        let dir = "tmp_snapshots";
+        let store = SnapshotStore::new(&SnapshotConfig {
+            dir: Some(dir.into()),
+            ..Default::default()
+        });
+        let url = Url::parse("https://host/list?page=2").unwrap();
+        assert!(store.load(&url).unwrap().is_none());
+
+        let page = Page {
+            url: url.to_string(),
+            final_url: "https://host/list/2".into(),
+            status: 200,
+            headers: vec![("Content-Type".into(), "text/html; charset=utf-8".into())],
+            fetched_at: Utc::now(),
+            charset: Some("UTF-8".into()),
+            body: b"<p>line one\nline two</p>\xff".to_vec(),
+        };
+        store.save(&page).unwrap();
+        let loaded = store.load(&url).unwrap().unwrap();
+        assert_eq!(loaded.body, page.body);
+        assert_eq!(loaded.final_url, page.final_url);
+        assert_eq!(loaded.fetched_at, page.fetched_at);
+        assert_eq!(loaded.charset, page.charset);
+        assert!(loaded.is_html());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::Path;
+
+use crate::models::DocumentType;
+
+/// How far into the body the `%PDF-` header may appear; readers such as
+/// Acrobat tolerate a little junk before it.
+pub const SNIFF_LEN: usize = 1024;
+const PDF_MAGIC: &[u8] = b"%PDF-";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// ODF packages start with an uncompressed `mimetype` entry
+const ODT_MIMETYPE: &[u8] = b"mimetypeapplication/vnd.oasis.opendocument.text";
+
+/// Media type of a `Content-Type` header value, lower-cased and without
+/// parameters ("text/html; charset=utf-8" → "text/html").
+pub fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Document type announced by a `Content-Type` header, if it names one.
+pub fn document_type_of(content_type: &str) -> Option<DocumentType> {
+    match media_type(content_type).as_str() {
+        "application/pdf" | "application/x-pdf" => Some(DocumentType::Pdf),
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+            Some(DocumentType::Docx)
+        }
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+            Some(DocumentType::Xlsx)
+        }
+        "application/vnd.oasis.opendocument.text" => Some(DocumentType::Odt),
+        "text/csv" | "application/csv" => Some(DocumentType::Csv),
+        "application/zip" | "application/x-zip-compressed" => Some(DocumentType::Zip),
+        _ => None,
+    }
+}
+
+/// Error and login pages, never a document.
+fn is_markup_content_type(content_type: &str) -> bool {
+    matches!(
+        media_type(content_type).as_str(),
+        "text/html" | "application/xhtml+xml"
+    )
+}
+
+pub fn has_pdf_magic(body: &[u8]) -> bool {
+    body[..body.len().min(SNIFF_LEN)]
+        .windows(PDF_MAGIC.len())
+        .any(|w| w == PDF_MAGIC)
+}
+
+/// Decide what a response body really is from its first bytes, the declared
+/// `Content-Type` and `name_hint` (server file name or URL path).
+/// PDFs are recognised by `%PDF-`; ZIP containers are told apart by content
+/// type, ODF `mimetype` entry or extension; CSV needs a CSV type or name and
+/// a body that is plain text. HTML is always rejected.
+/// Returns a human-readable reason when the body is not a known document.
+pub fn detect_document(
+    content_type: Option<&str>,
+    head: &[u8],
+    name_hint: &str,
+) -> Result<DocumentType, String> {
+    if let Some(ct) = content_type.filter(|ct| is_markup_content_type(ct)) {
+        return Err(format!("Content-Type is `{}`, not a document", media_type(ct)));
+    }
+    let declared = content_type.and_then(document_type_of);
+    let by_name = Path::new(name_hint)
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(DocumentType::from_extension);
+
+    if has_pdf_magic(head) {
+        return Ok(DocumentType::Pdf);
+    }
+    if head.starts_with(ZIP_MAGIC) {
+        if head.get(30..30 + ODT_MIMETYPE.len()) == Some(ODT_MIMETYPE) {
+            return Ok(DocumentType::Odt);
+        }
+        let office = |t: Option<DocumentType>| {
+            t.filter(|t| matches!(t, DocumentType::Docx | DocumentType::Xlsx | DocumentType::Odt))
+        };
+        return Ok(office(declared).or(office(by_name)).unwrap_or(DocumentType::Zip));
+    }
+    let is_csv = declared == Some(DocumentType::Csv)
+        || (by_name == Some(DocumentType::Csv) && declared.is_none());
+    if is_csv && looks_like_text(head) {
+        return Ok(DocumentType::Csv);
+    }
+
+    Err(match content_type {
+        Some(ct) => format!(
+            "Content-Type is `{}` and the body is not a recognised document",
+            media_type(ct)
+        ),
+        None => "body is not a recognised document".to_owned(),
+    })
+}
+
+/// No NUL bytes and no leading markup.
+fn looks_like_text(head: &[u8]) -> bool {
+    !head.contains(&0) && !String::from_utf8_lossy(head).trim_start().starts_with('<')
+}
+
+/// File extension for a quarantined body, so it can be opened for inspection.
+pub fn extension_for(content_type: Option<&str>) -> &'static str {
+    match content_type.map(media_type).as_deref() {
+        Some("text/html") | Some("application/xhtml+xml") => "html",
+        Some("text/plain") => "txt",
+        Some("application/json") => "json",
+        Some("application/xml") | Some("text/xml") => "xml",
+        _ => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_real_pdfs() {
+        let pdf = Ok(DocumentType::Pdf);
+        assert_eq!(detect_document(Some("application/pdf"), b"%PDF-1.7\n...", "a.pdf"), pdf);
+        assert_eq!(detect_document(Some("application/octet-stream"), b"%PDF-1.4", "x"), pdf);
+        assert_eq!(detect_document(None, b"\r\n%PDF-1.4", "/download"), pdf);
+    }
+
+    #[test]
+    fn rejects_html_error_pages() {
+        let login = b"<!DOCTYPE html><html><body>Please log in</body></html>";
+        assert!(detect_document(Some("text/html; charset=utf-8"), login, "a.pdf").is_err());
+        // a lying Content-Type is caught by the magic bytes
+        assert!(detect_document(Some("application/pdf"), login, "a.pdf").is_err());
+        // an HTML page is not a CSV just because of its name
+        assert!(detect_document(None, login, "table.csv").is_err());
+        assert_eq!(extension_for(Some("text/html; charset=utf-8")), "html");
+    }
+
+    #[test]
+    fn tells_zip_containers_apart() {
+        let zip = b"PK\x03\x04\x14\x00\x00\x00";
+        let docx = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+        assert_eq!(detect_document(Some(docx), zip, "x"), Ok(DocumentType::Docx));
+        assert_eq!(detect_document(None, zip, "/files/t.xlsx"), Ok(DocumentType::Xlsx));
+        assert_eq!(detect_document(Some("application/zip"), zip, "all.zip"), Ok(DocumentType::Zip));
+
+        let mut odt = vec![0u8; 30];
+        odt[..4].copy_from_slice(ZIP_MAGIC);
+        odt.extend_from_slice(ODT_MIMETYPE);
+        assert_eq!(detect_document(None, &odt, "x"), Ok(DocumentType::Odt));
+    }
+
+    #[test]
+    fn recognises_csv() {
+        let csv = b"name;country\nACME;France\n";
+        assert_eq!(detect_document(Some("text/csv"), csv, "x"), Ok(DocumentType::Csv));
+        assert_eq!(detect_document(None, csv, "projects.csv"), Ok(DocumentType::Csv));
+        assert!(detect_document(Some("text/plain"), csv, "notes").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Minimal HTTP/1.1 server for tests: every connection serves one request,
+//! answered by a handler closure. Good enough for reqwest, nothing more.
+
+use std::{collections::HashMap, future::Future, net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lower-cased
+    pub headers: HashMap<String, String>,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Announce the full length but hang up after this many body bytes
+    pub cut_after: Option<usize>,
+}
+
+impl TestResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            cut_after: None,
+        }
+    }
+
+    /// Simulate a connection dropped mid-body.
+    pub fn cut_after(mut self, bytes: usize) -> Self {
+        self.cut_after = Some(bytes);
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+/// Spawn the server on an ephemeral port and return its base URL.
+pub async fn serve<F>(handler: F) -> String
+where
+    F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+{
+    serve_async(move |request| std::future::ready(handler(&request))).await
+}
+
+/// Like [`serve`], for handlers that await, e.g. to answer slowly without
+/// blocking a runtime worker.
+pub async fn serve_async<F, R>(handler: F) -> String
+where
+    F: Fn(TestRequest) -> R + Send + Sync + 'static,
+    R: Future<Output = TestResponse> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let Some(request) = read_request(&mut stream).await else {
+                    return;
+                };
+                let method = request.method.clone();
+                let response = handler(request).await;
+                let mut head = format!("HTTP/1.1 {} X\r\n", response.status);
+                for (name, value) in &response.headers {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str(&format!(
+                    "Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.body.len()
+                ));
+                let _ = stream.write_all(head.as_bytes()).await;
+                if method != "HEAD" {
+                    let end = response.cut_after.unwrap_or(response.body.len());
+                    let _ = stream.write_all(&response.body[..end]).await;
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<TestRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    let mut lines = text.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_owned();
+    let path = request_line.next()?.to_owned();
+    let headers = lines
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_owned()))
+        .collect();
+
+    Some(TestRequest {
+        method,
+        path,
+        headers,
+    })
+}
@@ -0,0 +1,670 @@
+//! WARC 1.1 archives of a run's HTTP traffic, and a reader that replays an
+//! archive as the offline source of a later run.
+//!
+//! Every request the run makes (pages, downloads, robots.txt files, HEAD
+//! checks) is written as a `request` record and a `response` record (a
+//! `revisit` record for 304 Not Modified). Each record has its own
+//! `urn:uuid` record ID and a SHA-256 block digest, and responses also carry
+//! a payload digest. A file ending in `.gz` holds one gzip member per
+//! record, as archiving tools expect.
+//!
+//! reqwest undoes chunked transfer encoding and follows redirects by itself,
+//! so a response is recorded without `Transfer-Encoding` under the final URL.
+//! `X-Requested-URI` names the URL originally asked for when it differs.
+//!
+//! A download resumed after a dropped connection is recorded as it happened:
+//! the interrupted response with `WARC-Truncated: disconnect`, then the
+//! `Range` request and its 206 response holding the rest of the body. The
+//! reader joins the parts again.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use reqwest::Response;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use url::Url;
+
+use crate::{
+    config::{HttpConfig, WarcConfig},
+    downloader::content_range_start,
+    http::{self, DEFAULT_USER_AGENT},
+    snapshot::Page,
+};
+
+const DEFAULT_DIR: &str = "backup/warc";
+const NOT_MODIFIED_PROFILE: &str = "http://netpreserve.org/warc/1.1/revisit/server-not-modified";
+const CONFORMS_TO: &str = "http://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/";
+
+/// One HTTP request and the response it got.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// `GET` or `HEAD`
+    pub method: &'static str,
+    /// URL the request was made for
+    pub requested: Url,
+    /// URL that answered, after redirects
+    pub url: Url,
+    /// Headers set on this request on top of the client's defaults
+    pub request_headers: Vec<(String, String)>,
+    pub version: String,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    pub remote_addr: Option<IpAddr>,
+    pub date: DateTime<Utc>,
+    /// The connection dropped before the whole body arrived
+    pub truncated: bool,
+}
+
+impl Exchange {
+    /// Capture the response to a GET request before its body is read.
+    pub fn new(requested: &Url, request_headers: Vec<(String, String)>, response: &Response) -> Self {
+        Self {
+            method: "GET",
+            requested: requested.clone(),
+            url: response.url().clone(),
+            request_headers,
+            version: format!("{:?}", response.version()),
+            status: response.status().as_u16(),
+            response_headers: response
+                .headers()
+                .iter()
+                .map(|(k, v)| (k.as_str().to_owned(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+                .collect(),
+            remote_addr: response.remote_addr().map(|addr| addr.ip()),
+            date: Utc::now(),
+            truncated: false,
+        }
+    }
+}
+
+/// Body of a recorded response.
+pub enum Payload<'a> {
+    Bytes(&'a [u8]),
+    /// A body streamed to disk, such as a download, from the given offset
+    /// on: a response resuming a download delivered only the end of the file
+    File(&'a Path, u64),
+}
+
+impl Payload<'_> {
+    fn len(&self) -> Result<u64> {
+        match self {
+            Payload::Bytes(bytes) => Ok(bytes.len() as u64),
+            Payload::File(path, offset) => Ok(fs::metadata(path)
+                .with_context(|| format!("Cannot read {}", path.display()))?
+                .len()
+                .saturating_sub(*offset)),
+        }
+    }
+
+    /// Hand the payload to `sink` chunk by chunk.
+    fn each_chunk(&self, mut sink: impl FnMut(&[u8]) -> io::Result<()>) -> Result<()> {
+        match self {
+            Payload::Bytes(bytes) => sink(bytes)?,
+            Payload::File(path, offset) => {
+                let mut file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+                file.seek(SeekFrom::Start(*offset))?;
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    sink(&buf[..n])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A record about to be written.
+struct Record<'a> {
+    kind: &'static str,
+    id: String,
+    date: DateTime<Utc>,
+    fields: Vec<(&'static str, String)>,
+    content_type: &'static str,
+    /// Start of the block: the HTTP head, or the whole block
+    head: Vec<u8>,
+    payload: Option<&'a Payload<'a>>,
+}
+
+/// Appends records to one WARC file; shared by concurrent downloads.
+pub struct WarcWriter {
+    path: PathBuf,
+    out: Mutex<BufWriter<File>>,
+    gzip: bool,
+    info_id: String,
+    /// Headers the client sends with every request
+    client_headers: Vec<(String, String)>,
+}
+
+impl WarcWriter {
+    /// A new archive in `[warc].dir`, named after the time the run started.
+    pub fn for_run(cfg: &WarcConfig, http: &HttpConfig) -> Result<Self> {
+        let dir = Path::new(cfg.dir.as_deref().unwrap_or(DEFAULT_DIR));
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        let extension = if cfg.gzip.unwrap_or(true) { "warc.gz" } else { "warc" };
+        let name = format!("scrape-{}.{extension}", Utc::now().format("%Y%m%dT%H%M%SZ"));
+        Self::create(&dir.join(name), http)
+    }
+
+    /// Start the archive at `path` with a `warcinfo` record. The records are
+    /// gzip-compressed when `path` ends in `.gz`.
+    pub fn create(path: &Path, http: &HttpConfig) -> Result<Self> {
+        let mut client_headers: Vec<_> = http::default_headers(http)?
+            .iter()
+            .map(|(k, v)| (k.as_str().to_owned(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+            .collect();
+        client_headers.sort();
+        let file = File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+
+        let writer = Self {
+            path: path.to_owned(),
+            out: Mutex::new(BufWriter::new(file)),
+            gzip: path.extension().is_some_and(|e| e == "gz"),
+            info_id: record_id(),
+            client_headers,
+        };
+        let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let info = format!(
+            "software: {DEFAULT_USER_AGENT}\r\nformat: WARC File Format 1.1\r\nconformsTo: {CONFORMS_TO}\r\n"
+        );
+        writer.write(Record {
+            kind: "warcinfo",
+            id: writer.info_id.clone(),
+            date: Utc::now(),
+            fields: vec![("WARC-Filename", filename)],
+            content_type: "application/warc-fields",
+            head: info.into_bytes(),
+            payload: None,
+        })?;
+        Ok(writer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `exchange` as a request record and a response record carrying
+    /// `payload`, or a `revisit` record if the server answered 304.
+    pub fn record(&self, exchange: &Exchange, payload: Payload) -> Result<()> {
+        let response_id = record_id();
+        let target = exchange.url.to_string();
+        let not_modified = exchange.status == 304;
+
+        let mut fields = vec![
+            ("WARC-Target-URI", target.clone()),
+            ("WARC-Warcinfo-ID", self.info_id.clone()),
+        ];
+        if let Some(ip) = exchange.remote_addr {
+            fields.push(("WARC-IP-Address", ip.to_string()));
+        }
+        if exchange.requested != exchange.url {
+            fields.push(("X-Requested-URI", exchange.requested.to_string()));
+        }
+        if not_modified {
+            fields.push(("WARC-Profile", NOT_MODIFIED_PROFILE.to_owned()));
+        }
+        if exchange.truncated {
+            fields.push(("WARC-Truncated", "disconnect".to_owned()));
+        }
+
+        self.write(Record {
+            kind: "request",
+            id: record_id(),
+            date: exchange.date,
+            fields: vec![
+                ("WARC-Target-URI", target),
+                ("WARC-Warcinfo-ID", self.info_id.clone()),
+                ("WARC-Concurrent-To", response_id.clone()),
+            ],
+            content_type: "application/http;msgtype=request",
+            head: self.request_head(exchange),
+            payload: None,
+        })?;
+        self.write(Record {
+            kind: if not_modified { "revisit" } else { "response" },
+            id: response_id,
+            date: exchange.date,
+            fields,
+            content_type: "application/http;msgtype=response",
+            head: response_head(exchange),
+            payload: (!not_modified).then_some(&payload),
+        })
+    }
+
+    fn request_head(&self, exchange: &Exchange) -> Vec<u8> {
+        let url = &exchange.url;
+        let target = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_owned(),
+        };
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+        let mut head = format!("{} {target} {}\r\nhost: {host}\r\n", exchange.method, exchange.version);
+        for (name, value) in self.client_headers.iter().chain(&exchange.request_headers) {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+
+    fn write(&self, record: Record) -> Result<()> {
+        let mut block_digest = Sha256::new();
+        let mut payload_digest = Sha256::new();
+        block_digest.update(&record.head);
+        let mut length = record.head.len() as u64;
+        if let Some(payload) = record.payload {
+            payload.each_chunk(|chunk| {
+                block_digest.update(chunk);
+                payload_digest.update(chunk);
+                Ok(())
+            })?;
+            length += payload.len()?;
+        }
+
+        let mut header = format!(
+            "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: {}\r\nWARC-Date: {}\r\n",
+            record.kind,
+            record.id,
+            record.date.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        for (name, value) in &record.fields {
+            header.push_str(&format!("{name}: {value}\r\n"));
+        }
+        header.push_str(&format!("WARC-Block-Digest: sha256:{}\r\n", base32(&block_digest.finalize())));
+        if record.payload.is_some() {
+            header.push_str(&format!("WARC-Payload-Digest: sha256:{}\r\n", base32(&payload_digest.finalize())));
+        }
+        header.push_str(&format!("Content-Type: {}\r\nContent-Length: {length}\r\n\r\n", record.content_type));
+
+        let emit = |out: &mut dyn Write| -> Result<()> {
+            out.write_all(header.as_bytes())?;
+            out.write_all(&record.head)?;
+            if let Some(payload) = record.payload {
+                payload.each_chunk(|chunk| out.write_all(chunk))?;
+            }
+            out.write_all(b"\r\n\r\n")?;
+            Ok(())
+        };
+        let mut out = self.out.lock().expect("WARC writer poisoned");
+        if self.gzip {
+            let mut gz = GzEncoder::new(&mut *out, Compression::default());
+            emit(&mut gz)?;
+            gz.finish()?;
+        } else {
+            emit(&mut *out)?;
+        }
+        // a crash keeps every record written so far
+        out.flush().with_context(|| format!("Cannot write {}", self.path.display()))
+    }
+}
+
+/// Status line and headers of the recorded response.
+fn response_head(exchange: &Exchange) -> Vec<u8> {
+    let reason = reqwest::StatusCode::from_u16(exchange.status)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or_default();
+    let mut head = format!("{} {} {reason}\r\n", exchange.version, exchange.status);
+    for (name, value) in &exchange.response_headers {
+        // the body is stored decoded
+        if !name.eq_ignore_ascii_case("transfer-encoding") {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+/// A random (version 4) UUID as a WARC record ID.
+fn record_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let h = hex::encode(bytes);
+    format!("<urn:uuid:{}-{}-{}-{}-{}>", &h[..8], &h[8..12], &h[12..16], &h[16..20], &h[20..])
+}
+
+/// RFC 4648 base32, the usual encoding of WARC digests.
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut out = String::new();
+    for chunk in bytes.chunks(5) {
+        let mut group = [0u8; 8];
+        group[3..3 + chunk.len()].copy_from_slice(chunk);
+        let bits = u64::from_be_bytes(group);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..8 {
+            if i < chars {
+                out.push(ALPHABET[(bits >> (35 - 5 * i)) as usize & 31] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// A record read back from an archive.
+#[derive(Debug)]
+pub struct WarcRecord {
+    pub headers: Vec<(String, String)>,
+    pub block: Vec<u8>,
+}
+
+impl WarcRecord {
+    /// First value of WARC header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Fail if a SHA-256 block digest does not match; other algorithms are
+    /// not checked.
+    fn verify(&self) -> Result<()> {
+        let Some(expected) = self.header("WARC-Block-Digest").and_then(|d| d.strip_prefix("sha256:")) else {
+            return Ok(());
+        };
+        let actual = base32(&Sha256::digest(&self.block));
+        if actual.trim_end_matches('=') != expected.trim_end_matches('=').to_ascii_uppercase() {
+            bail!(
+                "record {} does not match its block digest",
+                self.header("WARC-Record-ID").unwrap_or("without ID")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Every record of the archive at `path` (plain or gzip-compressed), with
+/// SHA-256 block digests verified.
+pub fn read_records(path: &Path) -> Result<Vec<WarcRecord>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let mut plain = BufReader::new(file);
+    let gzipped = plain.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let mut reader: Box<dyn BufRead> = if gzipped {
+        Box::new(BufReader::new(MultiGzDecoder::new(plain)))
+    } else {
+        Box::new(plain)
+    };
+
+    let mut records = Vec::new();
+    let mut line = String::new();
+    loop {
+        // records are separated by blank lines
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(records);
+            }
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+        if !line.starts_with("WARC/1.") {
+            bail!("{}: expected a WARC record, found {:?}", path.display(), line.trim_end());
+        }
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let field = line.trim_end_matches(['\r', '\n']);
+            if field.is_empty() {
+                break;
+            }
+            let (name, value) = field
+                .split_once(':')
+                .with_context(|| format!("{}: malformed WARC header {field:?}", path.display()))?;
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+        let mut record = WarcRecord { headers, block: Vec::new() };
+        let length: usize = record
+            .header("Content-Length")
+            .and_then(|l| l.parse().ok())
+            .with_context(|| format!("{}: record without a valid Content-Length", path.display()))?;
+        record.block = vec![0; length];
+        reader
+            .read_exact(&mut record.block)
+            .with_context(|| format!("{}: truncated record", path.display()))?;
+        record.verify().with_context(|| path.display().to_string())?;
+        records.push(record);
+    }
+}
+
+/// The responses of a WARC file, by URL, for replay.
+pub struct WarcArchive {
+    pages: HashMap<String, Page>,
+    /// Responses to HEAD requests
+    heads: HashMap<String, Page>,
+    /// URLs whose last answer was 304 Not Modified
+    not_modified: HashSet<String>,
+}
+
+impl WarcArchive {
+    /// Index the `response` and `revisit` records of the archive at `path`.
+    /// A URL fetched more than once replays its last response; the parts of
+    /// a resumed download are joined, and a body that never arrived whole is
+    /// left out.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut archive = Self {
+            pages: HashMap::new(),
+            heads: HashMap::new(),
+            not_modified: HashSet::new(),
+        };
+        let mut head_responses = HashSet::new();
+        let mut truncated = HashSet::new();
+        for record in read_records(path)? {
+            let Some(target) = record.header("WARC-Target-URI") else {
+                continue;
+            };
+            let requested = record.header("X-Requested-URI").unwrap_or(target);
+            let mut keys = vec![normalize(requested), normalize(target)];
+            keys.dedup();
+            match record.header("WARC-Type") {
+                Some("request") if record.block.starts_with(b"HEAD ") => {
+                    head_responses.extend(record.header("WARC-Concurrent-To").map(str::to_owned));
+                    continue;
+                }
+                Some("revisit") => {
+                    for key in keys {
+                        archive.pages.remove(&key);
+                        archive.not_modified.insert(key);
+                    }
+                    continue;
+                }
+                Some("response") => {}
+                _ => continue,
+            }
+
+            let mut page = parse_response(&record.block)
+                .with_context(|| format!("Cannot parse the response for {target}"))?;
+            page.final_url = target.to_owned();
+            page.url = requested.to_owned();
+            if let Some(date) = record.header("WARC-Date").and_then(|d| DateTime::parse_from_rfc3339(d).ok()) {
+                page.fetched_at = date.with_timezone(&Utc);
+            }
+            if record.header("WARC-Record-ID").is_some_and(|id| head_responses.contains(id)) {
+                for key in keys {
+                    archive.heads.insert(key, page.clone());
+                }
+                continue;
+            }
+            let cut = record.header("WARC-Truncated").is_some();
+            if page.status == 206 {
+                // the rest of an interrupted download
+                let start = page.header("content-range").and_then(content_range_start);
+                for key in keys {
+                    match archive.pages.get_mut(&key) {
+                        Some(whole) if truncated.contains(&key) && Some(whole.body.len() as u64) == start => {
+                            whole.body.extend_from_slice(&page.body);
+                            whole.fetched_at = page.fetched_at;
+                            if !cut {
+                                truncated.remove(&key);
+                            }
+                        }
+                        _ => {
+                            archive.pages.remove(&key);
+                        }
+                    }
+                }
+                continue;
+            }
+            for key in keys {
+                if cut {
+                    truncated.insert(key.clone());
+                } else {
+                    truncated.remove(&key);
+                }
+                archive.not_modified.remove(&key);
+                archive.pages.insert(key, page.clone());
+            }
+        }
+        archive.pages.retain(|key, _| !truncated.contains(key));
+        Ok(archive)
+    }
+
+    pub fn get(&self, url: &Url) -> Option<Page> {
+        self.pages.get(url.as_str()).cloned()
+    }
+
+    /// The answer to a HEAD request for `url`.
+    pub fn head(&self, url: &Url) -> Option<Page> {
+        self.heads.get(url.as_str()).cloned()
+    }
+
+    /// Whether the last request for `url` was answered with 304 Not Modified.
+    pub fn not_modified(&self, url: &Url) -> bool {
+        self.not_modified.contains(url.as_str())
+    }
+}
+
+fn normalize(url: &str) -> String {
+    Url::parse(url).map(String::from).unwrap_or_else(|_| url.to_owned())
+}
+
+/// Split an `application/http` response block into status, headers and body.
+fn parse_response(block: &[u8]) -> Result<Page> {
+    let end = block
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .context("no end of the HTTP head")?;
+    let head = String::from_utf8_lossy(&block[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse().ok())
+        .context("no HTTP status line")?;
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+        .collect();
+    Ok(Page {
+        url: String::new(),
+        final_url: String::new(),
+        status,
+        headers,
+        fetched_at: Utc::now(),
+        charset: None,
+        body: block[end + 4..].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(url: &str, status: u16) -> Exchange {
+        let url = Url::parse(url).unwrap();
+        Exchange {
+            method: "GET",
+            requested: url.clone(),
+            url,
+            request_headers: Vec::new(),
+            version: "HTTP/1.1".into(),
+            status,
+            response_headers: vec![
+                ("content-type".into(), "text/html".into()),
+                ("transfer-encoding".into(), "chunked".into()),
+            ],
+            remote_addr: None,
+            date: Utc::now(),
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn encodes_base32() {
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI======");
+        assert_eq!(base32(b"fooba"), "MZXW6YTB");
+    }
+
+    #[test]
+    fn writes_and_replays_archives() {
+        for name in ["tmp_test.warc", "tmp_test.warc.gz"] {
+            let path = Path::new(name);
+            let writer = WarcWriter::create(path, &HttpConfig::default()).unwrap();
+            let page = exchange("https://host/list?page=2", 200);
+            writer.record(&page, Payload::Bytes(b"<p>one</p>")).unwrap();
+            let mut redirected = exchange("https://host/final", 200);
+            redirected.requested = Url::parse("https://host/old").unwrap();
+            fs::write("tmp_warc_body", "<p>two</p>").unwrap();
+            writer.record(&redirected, Payload::File(Path::new("tmp_warc_body"), 0)).unwrap();
+            writer.record(&exchange("https://host/a.pdf", 304), Payload::Bytes(b"")).unwrap();
+            drop(writer);
+
+            let records = read_records(path).unwrap();
+            let kinds: Vec<_> = records.iter().map(|r| r.header("WARC-Type").unwrap()).collect();
+            assert_eq!(
+                kinds,
+                ["warcinfo", "request", "response", "request", "response", "request", "revisit"]
+            );
+            assert_eq!(records[1].header("WARC-Concurrent-To"), records[2].header("WARC-Record-ID"));
+            assert_ne!(records[2].header("WARC-Record-ID"), records[4].header("WARC-Record-ID"));
+            assert!(records[2].header("WARC-Payload-Digest").unwrap().starts_with("sha256:"));
+            assert!(String::from_utf8_lossy(&records[1].block).starts_with("GET /list?page=2 HTTP/1.1\r\nhost: host\r\n"));
+            assert!(!String::from_utf8_lossy(&records[2].block).contains("transfer-encoding"));
+
+            let archive = WarcArchive::open(path).unwrap();
+            let replayed = archive.get(&Url::parse("https://host/list?page=2").unwrap()).unwrap();
+            assert_eq!((replayed.status, replayed.text().as_str()), (200, "<p>one</p>"));
+            assert!(replayed.is_html());
+            let moved = archive.get(&Url::parse("https://host/old").unwrap()).unwrap();
+            assert_eq!((moved.final_url.as_str(), moved.text().as_str()), ("https://host/final", "<p>two</p>"));
+            assert!(archive.get(&Url::parse("https://host/a.pdf").unwrap()).is_none());
+            assert!(archive.not_modified(&Url::parse("https://host/a.pdf").unwrap()));
+
+            fs::remove_file(path).unwrap();
+            fs::remove_file("tmp_warc_body").unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_records() {
+        let path = Path::new("tmp_tampered.warc");
+        let writer = WarcWriter::create(path, &HttpConfig::default()).unwrap();
+        writer.record(&exchange("https://host/", 200), Payload::Bytes(b"<p>original</p>")).unwrap();
+        drop(writer);
+        let tampered = fs::read_to_string(path).unwrap().replace("original", "modified");
+        fs::write(path, tampered).unwrap();
+
+        let err = read_records(path).unwrap_err();
+        assert!(format!("{err:#}").contains("does not match its block digest"));
+
+        fs::remove_file(path).unwrap();
+    }
+}
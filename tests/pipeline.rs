@@ -0,0 +1,124 @@
+//! End-to-end run of the binary against the fixture site in
+//! `tests/fixtures/site`: scrape → download → parse → process, then an
+//! offline re-extraction from the stored snapshots. The fixture page links
+//! its fact sheet with an absolute URL on the real host, which must be
+//! served by the fixture server as well. Everything the runs store stays in
+//! `backup/fixtures`, apart from what a real run would use.
+
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const SITE: &str = "https://defence-industry-space.ec.europa.eu";
+
+const CONFIG: &str = r#"
+url = "https://defence-industry-space.ec.europa.eu/projects/"
+timeout = 10
+selector = "a"
+pdf_selector = "data-wt-preview"
+
+[retry]
+max_attempts = 1
+"#;
+
+fn run(work: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-webscraper"))
+        .args(args)
+        .current_dir(work)
+        .output()
+        .expect("binary runs");
+    assert!(
+        output.status.success(),
+        "run failed:\n{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn read_json(path: PathBuf) -> Value {
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    serde_json::from_str(&text).unwrap()
+}
+
+#[test]
+fn scrapes_downloads_parses_and_processes_fixture_site() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/site");
+    let work = Path::new(env!("CARGO_TARGET_TMPDIR")).join("pipeline");
+    let _ = fs::remove_dir_all(&work);
+    fs::create_dir_all(&work).unwrap();
+    fs::write(work.join("config.toml"), CONFIG).unwrap();
+    let backup = work.join("backup/fixtures");
+
+    let online = run(&work, &["--fixtures", fixtures.to_str().unwrap(), "--process-pdfs"]);
+    let stdout = String::from_utf8_lossy(&online.stdout);
+    let target = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("Target URL: "))
+        .expect("target URL printed")
+        .to_owned();
+    assert_eq!(target, format!("{SITE}/projects/"));
+    assert!(stdout.contains(&format!("in place of {SITE}")), "{stdout}");
+    assert!(backup.join("snapshots").is_dir());
+
+    let elements = read_json(backup.join("extracted_elements.json"));
+    let contents: Vec<&str> = elements
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        contents,
+        ["ALPHA-SHIELD fact sheet", "Withdrawn project fact sheet", "About the fund"]
+    );
+
+    let absolute = elements[0]["attributes"]["href"].as_str().unwrap();
+    assert_eq!(absolute, format!("{SITE}/projects/alpha-shield.pdf"));
+
+    let report = read_json(backup.join("download_report.json"));
+    assert_eq!((report["downloaded"].as_u64(), report["failed"].as_u64()), (Some(1), Some(1)));
+    let urls: Vec<&str> = report["records"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(urls, [absolute.to_owned(), format!("{SITE}/projects/withdrawn.pdf")]);
+    assert!(backup.join("alpha-shield.pdf").exists());
+
+    let texts = read_json(backup.join("pdf_text.json"));
+    let text = texts[0]["text"].as_str().unwrap();
+    assert!(text.contains("Orbital Systems (Coordinator) Belgium"), "{text}");
+
+    let summary = read_json(backup.join("edf_summary.json"));
+    let project = &summary["projects"][0];
+    assert_eq!(summary["total_projects"], 1);
+    assert_eq!(project["project_name"], "ALPHA-SHIELD");
+    assert_eq!(project["duration_months"], 36);
+    assert_eq!(project["max_eu_contribution"], 10_000_000.0);
+    assert_eq!(project["consortium_members"][0]["name"], "Orbital Systems");
+    assert_eq!(project["consortium_members"][0]["is_coordinator"], true);
+
+    // the pages are snapshotted, so the extraction reruns without a server
+    fs::remove_file(backup.join("extracted_elements.json")).unwrap();
+    let offline = run(
+        &work,
+        &["--offline", "--fixtures", fixtures.to_str().unwrap(), "--url", &target, "--selector", "li a"],
+    );
+    assert!(!String::from_utf8_lossy(&offline.stdout).contains("Using cached scrape"));
+    assert!(backup.join("extracted_elements.json").exists());
+    // neither stand-in pages nor re-extracted archives pass for a fresh scrape
+    let real = work.join("backup");
+    let cached = fs::read_dir(real.join("scrape_cache")).map_or(0, |d| d.count());
+    assert_eq!(cached, 0);
+    // and a real run finds no stand-in snapshots, downloads or manifest entries
+    for stored in ["snapshots", "download_manifest.json", "alpha-shield.pdf", "extracted_elements.json"] {
+        assert!(!real.join(stored).exists(), "{stored} written outside backup/fixtures");
+    }
+
+    fs::remove_dir_all(&work).unwrap();
+}